@@ -1,8 +1,284 @@
 use anyhow::{Context, Result, anyhow};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use log::{error, info};
-use ringbuf::{HeapRb, Producer};
+use cpal::{FromSample, SampleFormat, SizedSample};
+use log::{error, info, warn};
+use ringbuf::HeapProducer;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Settings for the `[audio]` config table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// Input device, matched by name or part of it; empty uses the system default
+    pub device: String,
+    /// How a multi-channel input becomes mono
+    pub channel: Channel,
+    /// Close the microphone after this long without a recording; 0 keeps it open
+    pub idle_timeout_ms: u64,
+    /// Audio from just before `start` to keep, while the microphone is still open
+    pub preroll_ms: u32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            device: String::new(),
+            channel: Channel::default(),
+            idle_timeout_ms: 30000,
+            preroll_ms: 300,
+        }
+    }
+}
+
+/// Which input channel is transcribed: `"average"`, `"max_energy"` or an index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    /// Mix all channels equally
+    #[default]
+    Average,
+    /// Follow the loudest channel, e.g. whichever mic of a conference array is
+    /// nearest the speaker
+    MaxEnergy,
+    #[serde(untagged)]
+    Index(u16),
+}
+
+// Smoothing of the per-channel energy `max_energy` compares, per callback
+const ENERGY_DECAY: f32 = 0.8;
+// How much louder another channel must be before `max_energy` switches to it
+const SWITCH_RATIO: f32 = 2.0;
+// Frames per input callback to allocate for; the default buffer sizes stay well below
+const CALLBACK_FRAMES: usize = 8192;
+
+/// Turns interleaved frames into mono as `Channel` says.
+pub struct Downmix {
+    channel: Channel,
+    channels: usize,
+    /// Smoothed energy of each channel, for `max_energy`
+    energy: Vec<f32>,
+    /// Energy of each channel in the current buffer
+    block: Vec<f32>,
+    current: usize,
+}
+
+impl Downmix {
+    pub fn new(channel: Channel, channels: u16) -> Result<Self> {
+        if let Channel::Index(index) = channel
+            && index >= channels
+        {
+            return Err(NoSuchChannel { index, channels }.into());
+        }
+        let channels = channels.max(1) as usize;
+        Ok(Self {
+            channel,
+            channels,
+            energy: vec![0.0; channels],
+            block: vec![0.0; channels],
+            current: 0,
+        })
+    }
+
+    /// Passes one mono sample per frame of `data` to `out`.
+    pub fn mix(&mut self, data: &[f32], mut out: impl FnMut(f32)) {
+        let frames = data.chunks_exact(self.channels);
+        match self.channel {
+            Channel::Average => {
+                for frame in frames {
+                    out(frame.iter().sum::<f32>() / self.channels as f32);
+                }
+            }
+            Channel::Index(index) => {
+                for frame in frames {
+                    out(frame[index as usize]);
+                }
+            }
+            Channel::MaxEnergy => {
+                self.follow_loudest(data);
+                for frame in frames {
+                    out(frame[self.current]);
+                }
+            }
+        }
+    }
+
+    /// Switches to a clearly louder channel; small differences keep the current one
+    /// so the signal does not flip between mics on every buffer.
+    fn follow_loudest(&mut self, data: &[f32]) {
+        self.block.fill(0.0);
+        for frame in data.chunks_exact(self.channels) {
+            for (energy, sample) in self.block.iter_mut().zip(frame) {
+                *energy += sample * sample;
+            }
+        }
+        for (energy, &block) in self.energy.iter_mut().zip(&self.block) {
+            *energy = *energy * ENERGY_DECAY + block * (1.0 - ENERGY_DECAY);
+        }
+
+        let loudest = (0..self.channels)
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap_or(0);
+        if self.energy[loudest] > self.energy[self.current] * SWITCH_RATIO {
+            self.current = loudest;
+        }
+    }
+}
+
+/// How the input callback reaches the event loop.
+#[derive(Clone, Default)]
+pub struct StreamSignals {
+    /// Notified after every write to the ring buffer
+    pub ready: Arc<Notify>,
+    /// Notified when the stream fails, e.g. because the device was unplugged
+    pub failed: Arc<Notify>,
+}
+
+/// An input device as listed by `devices` and `list_devices`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    /// The system default input
+    #[serde(default)]
+    pub default: bool,
+    /// The device the daemon is capturing from
+    #[serde(default)]
+    pub active: bool,
+    /// Supported channel counts, sample rates and formats
+    #[serde(default)]
+    pub configs: Vec<String>,
+}
+
+/// Lists the input devices, away from the caller's thread.
+pub type DeviceLister = Box<dyn FnOnce() -> Result<Vec<DeviceInfo>> + Send>;
+
+/// Where captured audio comes from: the sound card, or a fixture in tests.
+pub trait AudioSource {
+    /// Starts capturing from `device` (empty for the default) into `producer`, mixed
+    /// down as `channel` says. Returns the name of the device opened and its sample rate.
+    fn open(
+        &mut self,
+        device: &str,
+        channel: Channel,
+        producer: HeapProducer<f32>,
+        signals: StreamSignals,
+    ) -> Result<(String, u32)>;
+
+    /// Stops capturing and releases the device.
+    fn close(&mut self);
+
+    /// Enumerating devices can block for a while, so the event loop runs the returned
+    /// lister on a blocking thread.
+    fn device_lister(&self) -> DeviceLister;
+
+    fn devices(&self) -> Result<Vec<DeviceInfo>> {
+        self.device_lister()()
+    }
+}
+
+/// A device name that matches no input device.
+#[derive(Debug)]
+pub struct UnknownDevice(pub String);
+
+impl fmt::Display for UnknownDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No input device matches \"{}\"", self.0)
+    }
+}
+
+impl std::error::Error for UnknownDevice {}
+
+/// A `channel` index the input device does not have.
+#[derive(Debug)]
+pub struct NoSuchChannel {
+    pub index: u16,
+    pub channels: u16,
+}
+
+impl fmt::Display for NoSuchChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Input has {} channels, no channel {}",
+            self.channels, self.index
+        )
+    }
+}
+
+impl std::error::Error for NoSuchChannel {}
+
+/// Picks the device a `device` setting means: an exact name, or else the first name
+/// containing it, ignoring case.
+pub fn match_device<'a>(names: &'a [String], wanted: &str) -> Option<&'a str> {
+    let wanted_lower = wanted.to_lowercase();
+    names
+        .iter()
+        .find(|name| *name == wanted)
+        .or_else(|| {
+            names
+                .iter()
+                .find(|name| name.to_lowercase().contains(&wanted_lower))
+        })
+        .map(String::as_str)
+}
+
+/// Every input device the default host knows about.
+pub fn list_devices() -> Result<Vec<DeviceInfo>> {
+    let host = cpal::default_host();
+    let default_name = host.default_input_device().and_then(|d| d.name().ok());
+
+    let mut devices = Vec::new();
+    for device in host
+        .input_devices()
+        .context("Failed to list input devices")?
+    {
+        let Ok(name) = device.name() else { continue };
+        let configs = device
+            .supported_input_configs()
+            .map(|configs| {
+                configs
+                    .map(|c| {
+                        format!(
+                            "{} ch, {}-{} Hz, {}",
+                            c.channels(),
+                            c.min_sample_rate().0,
+                            c.max_sample_rate().0,
+                            c.sample_format()
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        devices.push(DeviceInfo {
+            default: default_name.as_deref() == Some(name.as_str()),
+            active: false,
+            name,
+            configs,
+        });
+    }
+    Ok(devices)
+}
+
+fn find_device(host: &cpal::Host, wanted: &str) -> Result<cpal::Device> {
+    if wanted.is_empty() {
+        return host
+            .default_input_device()
+            .ok_or_else(|| anyhow!("No input device found"));
+    }
+
+    let mut devices: Vec<(String, cpal::Device)> = host
+        .input_devices()
+        .context("Failed to list input devices")?
+        .filter_map(|d| Some((d.name().ok()?, d)))
+        .collect();
+    let names: Vec<String> = devices.iter().map(|(name, _)| name.clone()).collect();
+    let index = match_device(&names, wanted)
+        .and_then(|name| names.iter().position(|n| n == name))
+        .ok_or_else(|| UnknownDevice(wanted.to_string()))?;
+    Ok(devices.swap_remove(index).1)
+}
 
 pub struct AudioEngine {
     stream: Option<cpal::Stream>,
@@ -12,18 +288,21 @@ impl AudioEngine {
     pub fn new() -> Result<Self> {
         Ok(Self { stream: None })
     }
+}
 
-    pub fn start(&mut self, mut producer: Producer<f32, Arc<HeapRb<f32>>>) -> Result<u32> {
+impl AudioSource for AudioEngine {
+    fn open(
+        &mut self,
+        device: &str,
+        channel: Channel,
+        producer: HeapProducer<f32>,
+        signals: StreamSignals,
+    ) -> Result<(String, u32)> {
         let host = cpal::default_host();
+        let device = find_device(&host, device)?;
+        let name = device.name().unwrap_or("Unknown".to_string());
 
-        let device = host
-            .default_input_device()
-            .ok_or_else(|| anyhow!("No input device found"))?;
-
-        info!(
-            "Using input device: {}",
-            device.name().unwrap_or("Unknown".to_string())
-        );
+        info!("Using input device: {}", name);
 
         let supported_config = device
             .default_input_config()
@@ -38,10 +317,13 @@ impl AudioEngine {
         };
 
         // Si el dispositivo NO soporta 16kHz o Mono directamente, usamos su config por defecto
-        // y el stream match manejará los canales.
-        let actual_config = if device.supported_input_configs()?.any(|c| {
-            c.channels() == 1 && c.min_sample_rate().0 <= 16000 && c.max_sample_rate().0 >= 16000
-        }) {
+        // y el stream match manejará los canales. Picking a channel needs all of them.
+        let actual_config = if channel == Channel::Average
+            && device.supported_input_configs()?.any(|c| {
+                c.channels() == 1
+                    && c.min_sample_rate().0 <= 16000
+                    && c.max_sample_rate().0 >= 16000
+            }) {
             info!("Forcing 16000Hz Mono...");
             config
         } else {
@@ -54,56 +336,209 @@ impl AudioEngine {
 
         info!("Input config: {:?}", actual_config);
 
-        let err_fn = |err| error!("an error occurred on stream: {}", err);
-
+        let downmix = Downmix::new(channel, channels)?;
         let stream = match sample_format {
-            cpal::SampleFormat::F32 => device.build_input_stream(
-                &actual_config,
-                move |data: &[f32], _: &_| {
-                    // Downmix: si hay más de 1 canal, promediamos o solo tomamos el primero
-                    for frame in data.chunks(channels as usize) {
-                        let sum: f32 = frame.iter().sum();
-                        let mono = sum / channels as f32;
-                        let _ = producer.push(mono);
-                    }
-                },
-                err_fn,
-                None,
-            )?,
-            cpal::SampleFormat::I16 => device.build_input_stream(
-                &actual_config,
-                move |data: &[i16], _: &_| {
-                    for frame in data.chunks(channels as usize) {
-                        let sum: f32 = frame.iter().map(|&s| s as f32 / i16::MAX as f32).sum();
-                        let mono = sum / channels as f32;
-                        let _ = producer.push(mono);
-                    }
-                },
-                err_fn,
-                None,
-            )?,
-            cpal::SampleFormat::U16 => device.build_input_stream(
-                &actual_config,
-                move |data: &[u16], _: &_| {
-                    for frame in data.chunks(channels as usize) {
-                        let sum: f32 = frame
-                            .iter()
-                            .map(|&s| (s as f32 - u16::MAX as f32 / 2.0) / (u16::MAX as f32 / 2.0))
-                            .sum();
-                        let mono = sum / channels as f32;
-                        let _ = producer.push(mono);
-                    }
-                },
-                err_fn,
-                None,
-            )?,
-            _ => return Err(anyhow!("Unsupported sample format")),
+            SampleFormat::F32 => {
+                build_stream::<f32>(&device, &actual_config, downmix, producer, signals)?
+            }
+            SampleFormat::F64 => {
+                build_stream::<f64>(&device, &actual_config, downmix, producer, signals)?
+            }
+            SampleFormat::I16 => {
+                build_stream::<i16>(&device, &actual_config, downmix, producer, signals)?
+            }
+            SampleFormat::I32 => {
+                build_stream::<i32>(&device, &actual_config, downmix, producer, signals)?
+            }
+            SampleFormat::U8 => {
+                build_stream::<u8>(&device, &actual_config, downmix, producer, signals)?
+            }
+            SampleFormat::U16 => {
+                build_stream::<u16>(&device, &actual_config, downmix, producer, signals)?
+            }
+            other => return Err(anyhow!("Unsupported sample format: {}", other)),
         };
 
         stream.play().context("Failed to start audio stream")?;
 
         self.stream = Some(stream);
 
-        Ok(sample_rate)
+        Ok((name, sample_rate))
+    }
+
+    fn close(&mut self) {
+        // Dropping the stream closes the device, which also turns off the
+        // microphone-in-use indicator
+        if self.stream.take().is_some() {
+            info!("Input stream closed");
+        }
+    }
+
+    fn device_lister(&self) -> DeviceLister {
+        Box::new(list_devices)
+    }
+}
+
+/// Builds an input stream for one sample format, converting to `f32` before the
+/// downmix.
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut downmix: Downmix,
+    mut producer: HeapProducer<f32>,
+    signals: StreamSignals,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let StreamSignals { ready, failed } = signals;
+    // Sized up front and reused between callbacks, so the audio thread does not
+    // allocate unless a callback brings more frames than that
+    let mut samples: Vec<f32> = Vec::with_capacity(CALLBACK_FRAMES * config.channels as usize);
+
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &_| {
+            samples.clear();
+            samples.extend(data.iter().map(|&s| s.to_sample::<f32>()));
+            downmix.mix(&samples, |mono| {
+                let _ = producer.push(mono);
+            });
+            ready.notify_one();
+        },
+        // A lost device is reported to the event loop, which reopens the
+        // stream; anything else (an xrun, a backend hiccup) leaves it running
+        move |err| match err {
+            cpal::StreamError::DeviceNotAvailable => {
+                error!("Input device went away: {}", err);
+                failed.notify_one();
+            }
+            cpal::StreamError::BackendSpecific { err } => {
+                warn!("Input stream error: {}", err);
+            }
+        },
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Accumulates input level and reports it at a fixed interval.
+pub struct LevelMeter {
+    window: usize,
+    count: usize,
+    sum_squares: f32,
+    peak: f32,
+}
+
+impl LevelMeter {
+    pub fn new(sample_rate: u32, interval_ms: u32) -> Self {
+        Self {
+            window: (sample_rate * interval_ms / 1000).max(1) as usize,
+            count: 0,
+            sum_squares: 0.0,
+            peak: 0.0,
+        }
+    }
+
+    /// Returns `(rms, peak)` once a full interval has been measured.
+    pub fn push(&mut self, samples: &[f32]) -> Option<(f32, f32)> {
+        for s in samples {
+            self.sum_squares += s * s;
+            self.peak = self.peak.max(s.abs());
+        }
+        self.count += samples.len();
+
+        if self.count < self.window {
+            return None;
+        }
+
+        let level = ((self.sum_squares / self.count as f32).sqrt(), self.peak);
+        self.count = 0;
+        self.sum_squares = 0.0;
+        self.peak = 0.0;
+        Some(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_exact_name_before_substring() {
+        let names = vec![
+            "USB Audio Device Analog Stereo".to_string(),
+            "USB Audio".to_string(),
+            "Built-in Audio Analog Stereo".to_string(),
+        ];
+        assert_eq!(match_device(&names, "USB Audio"), Some("USB Audio"));
+        assert_eq!(
+            match_device(&names, "built-in"),
+            Some("Built-in Audio Analog Stereo")
+        );
+        assert_eq!(match_device(&names, "webcam"), None);
+    }
+
+    fn mix(downmix: &mut Downmix, data: &[f32]) -> Vec<f32> {
+        let mut out = Vec::new();
+        downmix.mix(data, |s| out.push(s));
+        out
+    }
+
+    #[test]
+    fn picks_a_channel_or_averages() {
+        let frames = [0.2, 0.4, -0.2, -0.4];
+        assert_eq!(
+            mix(&mut Downmix::new(Channel::Average, 2).unwrap(), &frames),
+            vec![0.3, -0.3]
+        );
+        assert_eq!(
+            mix(&mut Downmix::new(Channel::Index(1), 2).unwrap(), &frames),
+            vec![0.4, -0.4]
+        );
+        let err = Downmix::new(Channel::Index(5), 2).err().unwrap();
+        assert!(err.is::<NoSuchChannel>());
+    }
+
+    #[test]
+    fn max_energy_follows_the_loud_channel() {
+        let mut downmix = Downmix::new(Channel::MaxEnergy, 3).unwrap();
+        // Channel 2 carries the speech, the others only hiss
+        let speech: Vec<f32> = (0..300)
+            .map(|i| match i % 3 {
+                2 => 0.5,
+                _ => 0.01,
+            })
+            .collect();
+        assert!(mix(&mut downmix, &speech).iter().all(|&s| s == 0.5));
+
+        // A slightly louder burst elsewhere does not steal the channel
+        let burst: Vec<f32> = (0..300)
+            .map(|i| match i % 3 {
+                0 => 0.6,
+                2 => 0.5,
+                _ => 0.01,
+            })
+            .collect();
+        assert!(mix(&mut downmix, &burst).iter().all(|&s| s == 0.5));
+    }
+
+    #[test]
+    fn reads_channel_from_config() {
+        let parse = |toml: &str| -> AudioConfig {
+            config::Config::builder()
+                .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+                .build()
+                .unwrap()
+                .try_deserialize()
+                .unwrap()
+        };
+        assert_eq!(parse("channel = 1").channel, Channel::Index(1));
+        assert_eq!(
+            parse("channel = \"max_energy\"").channel,
+            Channel::MaxEnergy
+        );
+        assert_eq!(parse("").channel, Channel::Average);
     }
 }
@@ -0,0 +1,191 @@
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CODEC_TYPE_NULL, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::resample::Resampler;
+
+/// Decodes a WAV, FLAC or OGG/Vorbis file into 16 kHz mono samples.
+pub fn load(path: &Path) -> Result<Vec<f32>> {
+    let is_wav = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+
+    let (samples, sample_rate) = if is_wav {
+        decode_wav(path)?
+    } else {
+        decode_compressed(path)?
+    };
+
+    info!(
+        "Decoded {} ({} samples at {}Hz)",
+        path.display(),
+        samples.len(),
+        sample_rate
+    );
+
+    let mut resampler = Resampler::new(sample_rate, 1024)?;
+    let mut output = resampler.process(&samples)?;
+    output.extend(resampler.flush()?);
+    Ok(output)
+}
+
+fn decode_wav(path: &Path) -> Result<(Vec<f32>, u32)> {
+    let mut reader = hound::WavReader::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let spec = reader.spec();
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<Result<_, _>>()
+            .context("Failed to read WAV samples")?,
+        hound::SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()
+                .context("Failed to read WAV samples")?
+        }
+    };
+
+    Ok((
+        downmix(&interleaved, spec.channels as usize),
+        spec.sample_rate,
+    ))
+}
+
+fn decode_compressed(path: &Path) -> Result<(Vec<f32>, u32)> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .context("Unsupported audio format")?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow!("No audio track found"))?;
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or_else(|| anyhow!("Unknown sample rate"))?;
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .context("Unsupported codec")?;
+
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(e) => return Err(e).context("Failed to read audio packet"),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(e)) => {
+                warn!("Skipping corrupt packet: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e).context("Failed to decode audio"),
+        };
+
+        let spec = *decoded.spec();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend(downmix(buffer.samples(), spec.channels.count()));
+    }
+
+    Ok((samples, sample_rate))
+}
+
+fn downmix(interleaved: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return interleaved.to_vec();
+    }
+    interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// A 440 Hz tone at half scale has an RMS of 0.5 / √2.
+    fn assert_tone(samples: &[f32], expected_len: usize) {
+        assert!(
+            samples.len().abs_diff(expected_len) <= 160,
+            "{} samples",
+            samples.len()
+        );
+        let level = rms(&samples[1600..samples.len() - 1600]);
+        assert!((level - 0.354).abs() < 0.02, "rms {}", level);
+    }
+
+    #[test]
+    fn wav_is_resampled_to_16k_mono() {
+        let dir = std::env::temp_dir().join("telora-audio-file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tone.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..44100 {
+            let t = i as f32 / 44100.0;
+            let sample = (0.5 * 32767.0 * (2.0 * std::f32::consts::PI * 440.0 * t).sin()) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        assert_tone(&load(&path).unwrap(), 16000);
+    }
+
+    #[test]
+    fn flac_is_decoded() {
+        // 250 ms of the same tone, 24 kHz stereo
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/audio/sine-24k-stereo.flac");
+        assert_tone(&load(&path).unwrap(), 4000);
+    }
+}
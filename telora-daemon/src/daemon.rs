@@ -0,0 +1,1490 @@
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use ringbuf::{HeapConsumer, HeapRb};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+
+use crate::audio::{
+    AudioSource, LevelMeter, NoSuchChannel, StreamSignals, UnknownDevice, match_device,
+};
+use crate::dictation::Dictation;
+use crate::dsp::Dsp;
+use crate::events::{Event, EventSender, emit};
+use crate::model_pool::resolve_model_path;
+use crate::partial::PartialTranscriber;
+use crate::postprocess::PostProcessor;
+use crate::resample::{self, Resampler};
+use crate::socket::{Command, StartError, StatusResponse, SttConfig, TranscriptionResult};
+use crate::subtitles;
+use crate::transcriber::{CancelToken, DecodeOptions};
+use crate::vad::{self, Vad};
+use crate::vocabulary::Vocabulary;
+use crate::voice_commands::CommandMatcher;
+use crate::worker::{Job, JobResult, Output, TranscribeJob, Work, Worker};
+
+// How often the input level is published to subscribers
+const LEVEL_INTERVAL_MS: u32 = 100;
+// Samples handed to the resampler at a time
+const CHUNK_SIZE: usize = 512;
+// Capacity of the ring buffer between the input callback and the loop
+const RING_CAPACITY: usize = 16000 * 30;
+// Wait between attempts to reopen a microphone that went away
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+// How often an idle microphone's buffer is cut back to the pre-roll; well within
+// RING_CAPACITY, so no new audio is dropped in between
+const PREROLL_TRIM_INTERVAL: Duration = Duration::from_secs(5);
+const SAMPLES_PER_MS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Idle,
+    Recording,
+    Processing,
+    Dictating,
+}
+
+/// A new config whose model the worker is loading. It replaces the current one once
+/// the model is ready, so a reload that fails leaves the daemon as it was.
+struct PendingConfig {
+    config: SttConfig,
+    /// Becomes `base_config` when the settings were reloaded rather than a profile picked
+    base: Option<SttConfig>,
+    postprocessor: PostProcessor,
+    dsp: Dsp,
+    dictation_vad: Vad,
+    vad: Vad,
+    response_tx: oneshot::Sender<Result<()>>,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Idle => "Idle",
+            State::Recording => "Recording",
+            State::Processing => "Processing",
+            State::Dictating => "Dictating",
+        }
+    }
+}
+
+/// The daemon's event loop and the state it owns.
+///
+/// The loop sleeps until a command, a worker result or (while capturing) new audio
+/// arrives, so an idle daemon does no work at all. The microphone is opened for a
+/// recording and closed again once it has been idle for `audio.idle_timeout_ms`. If the
+/// device goes away, the stream is reopened, on the default device if the configured one is gone.
+pub struct Daemon {
+    /// Settings as loaded, and with the active profile applied
+    base_config: SttConfig,
+    config: SttConfig,
+    worker: Worker,
+    results_rx: mpsc::UnboundedReceiver<JobResult>,
+    source: Box<dyn AudioSource>,
+    /// Filled by the input callback; `None` while the microphone is closed
+    consumer: Option<HeapConsumer<f32>>,
+    signals: StreamSignals,
+    /// Name and sample rate of the open device
+    device: String,
+    sample_rate: u32,
+    /// When the idle microphone will be closed
+    close_at: Option<time::Instant>,
+    /// When to try again to open a microphone that is needed but failed
+    reconnect_at: Option<time::Instant>,
+    /// When to next drop idle audio older than the pre-roll
+    trim_at: time::Instant,
+    resampler: Resampler,
+    /// Filters between the resampler and the recording
+    dsp: Dsp,
+    /// Follows the speech in a recording, to trim the silence around it
+    vad: Vad,
+    events: EventSender,
+
+    state: State,
+    audio_buffer: Vec<f32>,
+    chunk_buf: Vec<f32>,
+    response_tx_opt: Option<oneshot::Sender<Result<TranscriptionResult, String>>>,
+    /// Reply to the start of the recording, sent once its model has loaded
+    start_tx: Option<oneshot::Sender<Result<(), StartError>>>,
+    pending_result: Option<Result<TranscriptionResult, String>>,
+    /// Streaming state of the current recording; away while the worker updates it
+    partial: Option<PartialTranscriber>,
+    dictation: Dictation,
+    level_meter: LevelMeter,
+    /// Language and probability from the most recent automatic detection
+    last_detection: Option<(String, f32)>,
+    /// Kept for `export` after the result has been delivered
+    last_result: Option<TranscriptionResult>,
+    vocabulary: Vocabulary,
+    /// Options for the current recording, including per-request overrides
+    decode_options: DecodeOptions,
+    postprocessor: PostProcessor,
+    commands: CommandMatcher,
+    /// Model decoding the current recording
+    recording_model: String,
+    /// Identifies the current recording; worker results for older ones are dropped
+    recording_id: u64,
+    cancel: CancelToken,
+    /// Stopped, but the final job has not been submitted yet
+    final_pending: bool,
+    /// Finish the streaming transcript rather than decode the whole recording again
+    streaming_final: bool,
+    /// A start that arrived while the previous recording was being transcribed
+    deferred_start: Option<Command>,
+    /// Leading silence cut from the recording in transcription, to restore timestamps
+    trimmed_ms: u64,
+    /// Reloads and profile switches waiting for their model, oldest first
+    pending_configs: VecDeque<PendingConfig>,
+}
+
+impl Daemon {
+    pub fn new(
+        base_config: SttConfig,
+        config: SttConfig,
+        worker: Worker,
+        results_rx: mpsc::UnboundedReceiver<JobResult>,
+        source: Box<dyn AudioSource>,
+        events: EventSender,
+    ) -> Result<Self> {
+        let sample_rate = resample::TARGET_SAMPLE_RATE;
+        let vocabulary = crate::load_vocabulary(&config);
+        let mut daemon = Self {
+            dictation: Dictation::new(config.vad()?, config.max_recording_seconds),
+            vad: config.vad()?,
+            level_meter: LevelMeter::new(sample_rate, LEVEL_INTERVAL_MS),
+            decode_options: config.decode_options(&vocabulary),
+            postprocessor: PostProcessor::new(&config.postprocess)?,
+            commands: CommandMatcher::new(&config.voice_commands),
+            dsp: Dsp::new(&config.dsp).context("Invalid [dsp] config")?,
+            recording_model: config.model_path.clone(),
+            vocabulary,
+            base_config,
+            config,
+            worker,
+            results_rx,
+            source,
+            consumer: None,
+            signals: StreamSignals::default(),
+            device: String::new(),
+            sample_rate,
+            close_at: None,
+            reconnect_at: None,
+            trim_at: time::Instant::now() + PREROLL_TRIM_INTERVAL,
+            resampler: Resampler::new(sample_rate, CHUNK_SIZE)
+                .context("Failed to init resampler")?,
+            events,
+            state: State::Idle,
+            audio_buffer: Vec::with_capacity(16000 * 30), // Linear buffer for recording
+            chunk_buf: Vec::with_capacity(CHUNK_SIZE),
+            response_tx_opt: None,
+            start_tx: None,
+            pending_result: None,
+            partial: None,
+            last_detection: None,
+            last_result: None,
+            recording_id: 0,
+            cancel: CancelToken::default(),
+            final_pending: false,
+            streaming_final: false,
+            deferred_start: None,
+            trimmed_ms: 0,
+            pending_configs: VecDeque::new(),
+        };
+        // Opened once up front so a missing device shows at startup
+        daemon
+            .open_stream()
+            .context("Failed to start audio engine")?;
+        Ok(daemon)
+    }
+
+    /// Runs until the command channel closes.
+    pub async fn run(mut self, mut commands: mpsc::Receiver<Command>) {
+        while self.step(&mut commands).await {}
+    }
+
+    /// Waits for the next thing to do and does it. Returns false once there will be
+    /// no more commands.
+    async fn step(&mut self, commands: &mut mpsc::Receiver<Command>) -> bool {
+        if self.state != State::Processing
+            && let Some(cmd) = self.deferred_start.take()
+        {
+            self.handle_command(cmd);
+            return true;
+        }
+
+        // Audio is only read while it is being used. An idle microphone fills the ring
+        // buffer, which is cut back to the pre-roll now and then rather than on every
+        // callback.
+        let capturing = matches!(self.state, State::Recording | State::Dictating);
+        let listening = self.consumer.is_some() && capturing;
+        let trimming = self.consumer.is_some() && !capturing && self.config.audio.preroll_ms > 0;
+        tokio::select! {
+            cmd = commands.recv() => match cmd {
+                Some(cmd) => self.handle_command(cmd),
+                None => return false,
+            },
+            Some(done) = self.results_rx.recv() => self.handle_result(done),
+            _ = self.signals.ready.notified(), if listening => self.process_audio(),
+            _ = time::sleep_until(self.trim_at), if trimming => {
+                self.trim_preroll();
+                self.trim_at = time::Instant::now() + PREROLL_TRIM_INTERVAL;
+            }
+            _ = self.signals.failed.notified(), if self.consumer.is_some() => {
+                warn!("Input stream failed, reconnecting");
+                self.close_stream();
+                self.reconnect();
+            }
+            _ = time::sleep_until(self.close_at.unwrap_or_else(time::Instant::now)),
+                if self.close_at.is_some() =>
+            {
+                info!(
+                    "Idle for {} ms, closing the microphone",
+                    self.config.audio.idle_timeout_ms
+                );
+                self.close_stream();
+            }
+            _ = time::sleep_until(self.reconnect_at.unwrap_or_else(time::Instant::now)),
+                if self.reconnect_at.is_some() =>
+            {
+                self.reconnect();
+            }
+        }
+
+        self.submit_final();
+        self.schedule_close();
+        true
+    }
+
+    /// Opens the microphone if it is closed, falling back to the default device when
+    /// the configured one cannot be opened. A new sample rate rebuilds the parts that
+    /// depend on it.
+    fn open_stream(&mut self) -> Result<()> {
+        if self.consumer.is_some() {
+            return Ok(());
+        }
+
+        let wanted = self.config.audio.device.clone();
+        let (device, sample_rate, consumer) = match self.open_device(&wanted) {
+            Ok(opened) => opened,
+            // A bad `channel` is the config's fault, not the device's
+            Err(e) if !wanted.is_empty() && !e.is::<NoSuchChannel>() => {
+                warn!(
+                    "Failed to open input device \"{}\": {:#}. Using the default device.",
+                    wanted, e
+                );
+                self.open_device("")?
+            }
+            Err(e) => return Err(e),
+        };
+
+        if sample_rate != self.sample_rate {
+            // Whisper expects 16 kHz; convert whatever rate the device ended up with
+            self.resampler =
+                Resampler::new(sample_rate, CHUNK_SIZE).context("Failed to init resampler")?;
+            self.level_meter = LevelMeter::new(sample_rate, LEVEL_INTERVAL_MS);
+            self.sample_rate = sample_rate;
+            if sample_rate != resample::TARGET_SAMPLE_RATE {
+                info!(
+                    "Resampling input from {}Hz to {}Hz",
+                    sample_rate,
+                    resample::TARGET_SAMPLE_RATE
+                );
+            }
+        }
+        self.device = device;
+        self.consumer = Some(consumer);
+        self.reconnect_at = None;
+        Ok(())
+    }
+
+    fn open_device(&mut self, device: &str) -> Result<(String, u32, HeapConsumer<f32>)> {
+        let (producer, consumer) = HeapRb::<f32>::new(RING_CAPACITY).split();
+        let (name, sample_rate) = self.source.open(
+            device,
+            self.config.audio.channel,
+            producer,
+            self.signals.clone(),
+        )?;
+        Ok((name, sample_rate, consumer))
+    }
+
+    fn close_stream(&mut self) {
+        self.source.close();
+        self.consumer = None;
+        self.close_at = None;
+    }
+
+    /// Reopens a closed microphone that is still needed, retrying until it works.
+    /// An idle one is left closed; the next recording opens it.
+    fn reconnect(&mut self) {
+        let capturing = matches!(self.state, State::Recording | State::Dictating);
+        if !capturing && self.config.audio.idle_timeout_ms != 0 {
+            self.reconnect_at = None;
+            return;
+        }
+
+        if let Err(e) = self.open_stream() {
+            error!("Failed to reopen the microphone: {:#}", e);
+            // Once per outage, not on every retry
+            if self.reconnect_at.is_none() {
+                emit(
+                    &self.events,
+                    Event::Error {
+                        message: format!("Microphone unavailable: {:#}", e),
+                    },
+                );
+            }
+            self.reconnect_at = Some(time::Instant::now() + RECONNECT_INTERVAL);
+        }
+    }
+
+    /// Arms the idle timer once the daemon is idle with the microphone open.
+    fn schedule_close(&mut self) {
+        let timeout = self.config.audio.idle_timeout_ms;
+        if self.state != State::Idle || self.consumer.is_none() || timeout == 0 {
+            self.close_at = None;
+        } else if self.close_at.is_none() {
+            self.close_at = Some(time::Instant::now() + Duration::from_millis(timeout));
+        }
+    }
+
+    /// Drops captured audio older than the configured pre-roll.
+    fn trim_preroll(&mut self) {
+        let keep = self.config.audio.preroll_ms as usize * self.sample_rate as usize / 1000;
+        if let Some(consumer) = &mut self.consumer {
+            let stale = consumer.len().saturating_sub(keep);
+            consumer.skip(stale);
+        }
+    }
+
+    fn set_state(&mut self, new_state: State) {
+        if self.state != new_state {
+            self.state = new_state;
+            emit(
+                &self.events,
+                Event::StateChanged {
+                    state: new_state.as_str().to_string(),
+                },
+            );
+        }
+    }
+
+    fn handle_command(&mut self, cmd: Command) {
+        // New settings to apply after a reload or profile switch
+        let mut reconfigure = None;
+        match cmd {
+            cmd @ (Command::Start { .. } | Command::DictateStart { .. })
+                if self.state == State::Processing =>
+            {
+                self.deferred_start = Some(cmd);
+            }
+            Command::Start {
+                options,
+                response_tx,
+            } => {
+                info!("Command: START");
+                match self.config.decode_options_with(&self.vocabulary, &options) {
+                    Ok(options) => self.decode_options = options,
+                    Err(e) => {
+                        let _ = response_tx.send(Err(StartError::InvalidOptions(e.to_string())));
+                        return;
+                    }
+                }
+                if !self.begin(options.model.as_deref(), response_tx) {
+                    return;
+                }
+                self.set_state(State::Recording);
+                self.partial = Some(PartialTranscriber::new(
+                    self.config.partial_interval_ms,
+                    self.config.partial_window_seconds,
+                ));
+                self.streaming_final = self.config.streaming;
+            }
+            Command::Stop { model, response_tx } => {
+                info!("Command: STOP");
+                if let Some(model) = model.filter(|_| self.state == State::Recording) {
+                    let model = requested_model(Some(&model), &self.config);
+                    if model != self.recording_model {
+                        self.recording_model = model;
+                        // Committed partial text came from the other model
+                        self.streaming_final = false;
+                    }
+                }
+                match self.state {
+                    State::Recording => {
+                        self.set_state(State::Processing);
+                        self.final_pending = true;
+                        self.response_tx_opt = Some(response_tx);
+                    }
+                    State::Processing => {
+                        self.response_tx_opt = Some(response_tx);
+                    }
+                    State::Dictating => {
+                        // Dictation results are pushed as they happen
+                        let _ = response_tx.send(Ok(TranscriptionResult::default()));
+                    }
+                    State::Idle => {
+                        if let Some(res) = self.pending_result.take() {
+                            let _ = response_tx.send(res);
+                        } else {
+                            let _ = response_tx.send(Ok(TranscriptionResult::default()));
+                        }
+                    }
+                }
+            }
+            Command::Cancel => {
+                info!("Command: CANCEL");
+                self.abandon();
+            }
+            Command::DictateStart {
+                options,
+                response_tx,
+            } => {
+                info!("Command: DICTATE_START");
+                match self.config.decode_options_with(&self.vocabulary, &options) {
+                    Ok(options) => self.decode_options = options,
+                    Err(e) => {
+                        let _ = response_tx.send(Err(StartError::InvalidOptions(e.to_string())));
+                        return;
+                    }
+                }
+                if !self.begin(options.model.as_deref(), response_tx) {
+                    return;
+                }
+                self.set_state(State::Dictating);
+                self.dictation.reset();
+            }
+            Command::DictateStop => {
+                info!("Command: DICTATE_STOP");
+                if self.state == State::Dictating {
+                    self.set_state(State::Idle);
+                    self.dictation.reset();
+                }
+            }
+            Command::Export {
+                format,
+                response_tx,
+            } => {
+                info!("Command: EXPORT");
+                let rendered = match &self.last_result {
+                    Some(result) => subtitles::render(result, format, &self.config.subtitles)
+                        .map_err(|e| e.to_string()),
+                    None => Err("No recording has been transcribed yet".to_string()),
+                };
+                let _ = response_tx.send(rendered);
+            }
+            Command::GetStatus { response_tx } => {
+                let _ = response_tx.send(self.status());
+            }
+            Command::ReloadConfig {
+                new_config,
+                response_tx,
+            } => {
+                info!("Command: REFRESH");
+                // A profile picked at runtime survives the reload while it still exists
+                let profile = if new_config.profiles.contains_key(&self.config.profile) {
+                    self.config.profile.clone()
+                } else {
+                    new_config.profile.clone()
+                };
+                match new_config.with_profile(&profile) {
+                    Ok(config) => reconfigure = Some((config, Some(*new_config), response_tx)),
+                    Err(e) => {
+                        let _ = response_tx.send(Err(e));
+                    }
+                }
+            }
+            Command::SetProfile { name, response_tx } => {
+                info!("Command: PROFILE {}", name);
+                match self.base_config.with_profile(&name) {
+                    Ok(config) => reconfigure = Some((config, None, response_tx)),
+                    Err(e) => {
+                        let _ = response_tx.send(Err(e));
+                    }
+                }
+            }
+            Command::ListDevices { response_tx } => {
+                info!("Command: LIST_DEVICES");
+                let list = self.source.device_lister();
+                let active = self.consumer.is_some().then(|| self.device.clone());
+                tokio::task::spawn_blocking(move || {
+                    let devices = list().map(|mut devices| {
+                        for device in &mut devices {
+                            device.active = active.as_ref() == Some(&device.name);
+                        }
+                        devices
+                    });
+                    let _ = response_tx.send(devices);
+                });
+            }
+            Command::SetDevice { name, response_tx } => {
+                info!("Command: SET_DEVICE {}", name);
+                let _ = response_tx.send(self.set_device(name));
+            }
+        }
+
+        if let Some((new_config, base, response_tx)) = reconfigure {
+            self.reconfigure(new_config, base, response_tx);
+        }
+    }
+
+    /// Common setup of `start` and `dictate_start`. The reply is sent by the worker once
+    /// the model is resident; capture starts right away. Returns false, after replying,
+    /// if the microphone cannot be opened.
+    fn begin(
+        &mut self,
+        model: Option<&str>,
+        response_tx: oneshot::Sender<Result<(), StartError>>,
+    ) -> bool {
+        if let Err(e) = self.open_stream() {
+            error!("Failed to open the microphone: {:#}", e);
+            let _ = response_tx.send(Err(StartError::Microphone(format!(
+                "Failed to open the microphone: {:#}",
+                e
+            ))));
+            return false;
+        }
+
+        // Capture starts now; the reply waits for the model, and a failed load
+        // abandons the recording
+        self.recording_id += 1;
+        self.recording_model = requested_model(model, &self.config);
+        self.worker.submit(Job::Load {
+            recording: self.recording_id,
+            model_path: self.recording_model.clone(),
+        });
+        self.start_tx = Some(response_tx);
+        self.cancel = CancelToken::default();
+        self.audio_buffer.clear();
+        self.resampler.reset();
+        self.dsp.reset();
+        self.vad.reset();
+        // Whatever the device captured while nobody was listening, but the pre-roll
+        self.trim_preroll();
+        self.final_pending = false;
+        self.response_tx_opt = None;
+        self.pending_result = None;
+        true
+    }
+
+    /// Drops the current recording: aborts its in-flight decode and skips queued ones.
+    fn abandon(&mut self) {
+        self.cancel.cancel();
+        self.recording_id += 1;
+        self.set_state(State::Idle);
+        self.audio_buffer.clear();
+        self.resampler.reset();
+        self.dsp.reset();
+        self.final_pending = false;
+        self.response_tx_opt = None;
+        self.start_tx = None;
+        self.pending_result = None;
+    }
+
+    fn status(&self) -> StatusResponse {
+        let models = self.worker.status();
+        StatusResponse {
+            active: true,
+            pid: std::process::id(),
+            model_path: self.config.model_path.clone(),
+            language: self.config.language.clone(),
+            max_recording_seconds: self.config.max_recording_seconds,
+            state: self.state.as_str().to_string(),
+            detected_language: self.last_detection.as_ref().map(|d| d.0.clone()),
+            language_probability: self.last_detection.as_ref().map(|d| d.1),
+            engine: models.engine,
+            backend: models.backend,
+            capabilities: models.capabilities,
+            vocabulary_path: self.vocabulary.path.clone(),
+            vocabulary_terms: self.vocabulary.terms.len(),
+            profile: self.config.profile.clone(),
+            output_mode: self.config.output_mode,
+            profiles: self.base_config.profiles.keys().cloned().collect(),
+            loaded_models: models.loaded_models,
+            audio_stream: if self.consumer.is_some() {
+                "open"
+            } else {
+                "closed"
+            }
+            .to_string(),
+            audio_device: if self.consumer.is_some() {
+                self.device.clone()
+            } else {
+                String::new()
+            },
+        }
+    }
+
+    /// Switches input devices, reopening the microphone right away if it is open.
+    fn set_device(&mut self, name: String) -> Result<String> {
+        // Checked up front, so a typo does not quietly fall back to the default
+        let devices = self.source.devices()?;
+        let names: Vec<String> = devices.iter().map(|d| d.name.clone()).collect();
+        let matched = if name.is_empty() {
+            devices.iter().find(|d| d.default).map(|d| d.name.as_str())
+        } else {
+            match_device(&names, &name)
+        };
+        let Some(matched) = matched.map(str::to_string) else {
+            return Err(UnknownDevice(name).into());
+        };
+
+        // Kept across profile switches, until the next reload
+        self.config.audio.device = name.clone();
+        self.base_config.audio.device = name;
+        if self.consumer.is_none() {
+            return Ok(matched);
+        }
+        self.close_stream();
+        if let Err(e) = self.open_stream() {
+            // Keep trying, rather than leave the microphone closed
+            self.reconnect();
+            return Err(e);
+        }
+        Ok(self.device.clone())
+    }
+
+    /// Has the worker load the model of a new config. The config takes effect once
+    /// the model is ready; until then, and if loading fails, the current one stays.
+    fn reconfigure(
+        &mut self,
+        config: SttConfig,
+        base: Option<SttConfig>,
+        response_tx: oneshot::Sender<Result<()>>,
+    ) {
+        let prepared = PostProcessor::new(&config.postprocess)
+            .context("Invalid [postprocess] config")
+            .and_then(|postprocessor| {
+                Ok((
+                    postprocessor,
+                    Dsp::new(&config.dsp).context("Invalid [dsp] config")?,
+                    config.vad()?,
+                    config.vad()?,
+                ))
+            });
+        let (postprocessor, dsp, dictation_vad, vad) = match prepared {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                let _ = response_tx.send(Err(e));
+                return;
+            }
+        };
+
+        // Compared with the config that will be in place when this one is applied
+        let previous = self
+            .pending_configs
+            .back()
+            .map_or(&self.config, |pending| &pending.config);
+        let model_changed =
+            config.model_path != previous.model_path || config.engine != previous.engine;
+        let preload_changed = config.model_pool.preload != previous.model_pool.preload;
+        if model_changed {
+            info!(
+                "Engine or model changed, switching to {}",
+                config.model_path
+            );
+        }
+        self.worker.submit(Job::Configure {
+            engine: config.engine.clone(),
+            pool: config.model_pool.clone(),
+            preload: preload_changed,
+            model_path: config.model_path.clone(),
+            announce: model_changed,
+        });
+        self.pending_configs.push_back(PendingConfig {
+            config,
+            base,
+            postprocessor,
+            dsp,
+            dictation_vad,
+            vad,
+            response_tx,
+        });
+    }
+
+    /// Switches to a config whose model has loaded.
+    fn apply_config(&mut self, pending: PendingConfig) {
+        let new_config = pending.config;
+        let profile_changed = new_config.profile != self.config.profile
+            || new_config.output_mode != self.config.output_mode;
+        let device_changed = new_config.audio.device != self.config.audio.device
+            || new_config.audio.channel != self.config.audio.channel;
+        let dsp_changed = new_config.dsp != self.config.dsp;
+
+        if let Some(base) = pending.base {
+            self.base_config = base;
+        }
+        self.config = new_config;
+        self.vocabulary = crate::load_vocabulary(&self.config);
+        self.decode_options = self.config.decode_options(&self.vocabulary);
+        self.postprocessor = pending.postprocessor;
+        self.commands = CommandMatcher::new(&self.config.voice_commands);
+        if dsp_changed {
+            self.dsp = pending.dsp;
+        }
+        self.last_detection = None;
+        self.dictation = Dictation::new(pending.dictation_vad, self.config.max_recording_seconds);
+        self.vad = pending.vad;
+
+        if profile_changed {
+            info!("Active profile: {}", self.config.profile);
+            emit(
+                &self.events,
+                Event::ProfileChanged {
+                    profile: self.config.profile.clone(),
+                    output_mode: self.config.output_mode,
+                },
+            );
+        }
+
+        if device_changed && self.consumer.is_some() {
+            // Reopened with the new settings now if it is in use, or by the next recording
+            self.close_stream();
+            if let Err(e) = self.open_stream() {
+                self.reconnect();
+                let _ = pending.response_tx.send(Err(e));
+                return;
+            }
+        }
+        let _ = pending.response_tx.send(Ok(()));
+    }
+
+    fn handle_result(&mut self, done: JobResult) {
+        // Configurations are not tied to a recording
+        let configured = matches!(done.output, Output::Configured(_));
+        if done.recording != self.recording_id && !configured {
+            // From a cancelled or earlier recording
+            return;
+        }
+        match done.output {
+            Output::Configured(outcome) => {
+                // The worker runs jobs in order, so this answers the oldest pending config
+                if let Some(pending) = self.pending_configs.pop_front() {
+                    match outcome {
+                        Ok(()) => self.apply_config(pending),
+                        Err(e) => {
+                            let _ = pending.response_tx.send(Err(e));
+                        }
+                    }
+                }
+            }
+            Output::Loaded(Ok(())) => {
+                if let Some(tx) = self.start_tx.take() {
+                    let _ = tx.send(Ok(()));
+                }
+            }
+            Output::Loaded(Err(e)) => {
+                let message = format!("{:#}", e);
+                if let Some(tx) = self.start_tx.take() {
+                    let _ = tx.send(Err(StartError::ModelLoad(message.clone())));
+                }
+                // A stop that came in while the model was loading
+                if let Some(tx) = self.response_tx_opt.take() {
+                    let _ = tx.send(Err(message));
+                }
+                self.abandon();
+            }
+            Output::Partial { partial, text } => {
+                self.partial = Some(partial);
+                match text {
+                    Ok(text) if !text.is_empty() && self.state == State::Recording => {
+                        emit(&self.events, Event::Partial { text });
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Partial transcription failed: {}", e),
+                }
+            }
+            Output::Utterance(transcript) => match transcript {
+                Ok(transcript) if !transcript.segments.is_empty() => {
+                    if let Some(p) = transcript.language_probability {
+                        self.last_detection = Some((transcript.language.clone(), p));
+                    }
+                    let result =
+                        TranscriptionResult::new(transcript, done.samples, done.latency_ms)
+                            .finish(&self.commands, &self.postprocessor);
+                    emit(
+                        &self.events,
+                        Event::Final {
+                            dictation: true,
+                            result,
+                        },
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Transcription failed: {}", e);
+                    emit(
+                        &self.events,
+                        Event::Error {
+                            message: format!("Transcription failed: {}", e),
+                        },
+                    );
+                }
+            },
+            Output::Final(transcript) => {
+                let result = match transcript {
+                    Ok(mut transcript) => {
+                        for segment in &mut transcript.segments {
+                            segment.start_ms += self.trimmed_ms;
+                            segment.end_ms += self.trimmed_ms;
+                        }
+                        Ok(
+                            TranscriptionResult::new(transcript, done.samples, done.latency_ms)
+                                .finish(&self.commands, &self.postprocessor),
+                        )
+                    }
+                    Err(e) => {
+                        error!("Transcription failed: {}", e);
+                        emit(
+                            &self.events,
+                            Event::Error {
+                                message: format!("Transcription failed: {}", e),
+                            },
+                        );
+                        Err(e.to_string())
+                    }
+                };
+
+                if let Ok(result) = &result {
+                    if let Some(p) = result.transcript.language_probability {
+                        self.last_detection = Some((result.transcript.language.clone(), p));
+                    }
+                    if !result.transcript.segments.is_empty() {
+                        self.last_result = Some(result.clone());
+                    }
+                    emit(
+                        &self.events,
+                        Event::Final {
+                            dictation: false,
+                            result: result.clone(),
+                        },
+                    );
+                }
+
+                if let Some(tx) = self.response_tx_opt.take() {
+                    let _ = tx.send(result);
+                    self.pending_result = None;
+                } else {
+                    self.pending_result = Some(result);
+                }
+
+                self.set_state(State::Idle);
+            }
+        }
+    }
+
+    /// Consumes every full chunk the input callback has written so far.
+    fn process_audio(&mut self) {
+        while let Some(consumer) = self.consumer.as_mut().filter(|c| c.len() >= CHUNK_SIZE) {
+            self.chunk_buf.extend(consumer.pop_iter().take(CHUNK_SIZE));
+
+            if matches!(self.state, State::Recording | State::Dictating)
+                && let Some((rms, peak)) = self.level_meter.push(&self.chunk_buf)
+            {
+                emit(&self.events, Event::AudioLevel { rms, peak });
+            }
+
+            match self.state {
+                State::Recording => self.record_chunk(),
+                State::Dictating => self.dictate_chunk(),
+                _ => {}
+            }
+
+            self.chunk_buf.clear();
+        }
+    }
+
+    fn record_chunk(&mut self) {
+        // Safety limit: User-defined or default maximum time
+        if self.audio_buffer.len() >= 16000 * self.config.max_recording_seconds as usize {
+            warn!(
+                "Audio buffer limit reached ({}s). Stopping recording automatically.",
+                self.config.max_recording_seconds
+            );
+            self.auto_stop("max_duration");
+            return;
+        }
+
+        match self.resampler.process(&self.chunk_buf) {
+            Ok(samples) => self.append(&samples),
+            Err(e) => error!("Failed to resample audio: {}", e),
+        }
+
+        if self.speech_ended() {
+            info!(
+                "{} ms of silence after speech. Stopping recording automatically.",
+                self.config.auto_stop_silence_ms
+            );
+            self.auto_stop("silence");
+            return;
+        }
+
+        // One update at a time: the state is away while the worker has it
+        let audio_len = self.audio_buffer.len();
+        if self.config.streaming
+            && let Some(partial) = self.partial.take_if(|p| p.is_due(audio_len))
+        {
+            self.submit(self.audio_buffer.clone(), Work::Partial(partial));
+        }
+    }
+
+    /// Whether enough silence has followed enough speech to end the recording.
+    fn speech_ended(&self) -> bool {
+        let silence = self.config.auto_stop_silence_ms as usize * SAMPLES_PER_MS;
+        let min_speech = self.config.auto_stop_min_speech_ms as usize * SAMPLES_PER_MS;
+        silence > 0
+            && self.vad.speech_samples() >= min_speech
+            && self.vad.trailing_silence().is_some_and(|s| s >= silence)
+    }
+
+    /// Ends the recording without a stop command.
+    fn auto_stop(&mut self, reason: &str) {
+        self.set_state(State::Processing);
+        self.final_pending = true;
+        // Notify client to stop UI and request result
+        emit(
+            &self.events,
+            Event::AutoStop {
+                reason: reason.to_string(),
+            },
+        );
+    }
+
+    /// Cuts utterances at speech end and queues each one for the worker.
+    fn dictate_chunk(&mut self) {
+        let utterance = match self.resampler.process(&self.chunk_buf) {
+            Ok(samples) => {
+                let samples = self.dsp.process(&samples);
+                self.dictation.push(&samples)
+            }
+            Err(e) => {
+                error!("Failed to resample audio: {}", e);
+                None
+            }
+        };
+
+        if let Some(utterance) = utterance {
+            info!("Utterance ended, queueing {} samples...", utterance.len());
+            self.submit(utterance, Work::Utterance);
+        }
+    }
+
+    /// Hands a stopped recording to the worker, once any partial update is back.
+    fn submit_final(&mut self) {
+        if self.state != State::Processing
+            || !self.final_pending
+            || (self.streaming_final && self.partial.is_none())
+        {
+            return;
+        }
+        self.final_pending = false;
+
+        match self.resampler.flush() {
+            Ok(samples) => self.append(&samples),
+            Err(e) => error!("Failed to resample audio: {}", e),
+        }
+        let tail = self.dsp.flush();
+        self.vad.is_voice_segment(&tail);
+        self.audio_buffer.extend_from_slice(&tail);
+
+        let streaming = if self.streaming_final {
+            self.partial.take()
+        } else {
+            None
+        };
+        let mut audio = std::mem::take(&mut self.audio_buffer);
+        self.trimmed_ms = self.trim_silence(&mut audio, streaming.is_some());
+        info!("Processing {} samples...", audio.len());
+        self.submit(audio, Work::Final(streaming));
+    }
+
+    /// Filters resampled audio into the recording.
+    fn append(&mut self, samples: &[f32]) {
+        let samples = self.dsp.process(samples);
+        self.vad.is_voice_segment(&samples);
+        self.audio_buffer.extend_from_slice(&samples);
+    }
+
+    /// Cuts the silence around the speech the VAD heard, keeping the configured
+    /// padding, and returns how much was cut from the start in milliseconds. A
+    /// streaming recording keeps its start, which the partial transcript covers,
+    /// and audio without any detected speech is left alone.
+    fn trim_silence(&self, audio: &mut Vec<f32>, streaming: bool) -> u64 {
+        if !self.config.vad.trim_silence {
+            return 0;
+        }
+        let Some(span) = self.vad.speech_span() else {
+            return 0;
+        };
+        let padding = self.config.vad.trim_padding_ms as usize * SAMPLES_PER_MS;
+        let keep = vad::padded(&span, padding, audio.len());
+        let start = if streaming { 0 } else { keep.start };
+        debug!(
+            "Trimmed {} ms of silence",
+            (audio.len() - (keep.end - start)) / SAMPLES_PER_MS
+        );
+        audio.truncate(keep.end);
+        audio.drain(..start);
+        (start / SAMPLES_PER_MS) as u64
+    }
+
+    fn submit(&self, audio: Vec<f32>, work: Work) {
+        self.worker.submit(Job::Transcribe(Box::new(TranscribeJob {
+            recording: self.recording_id,
+            model_path: self.recording_model.clone(),
+            audio,
+            options: DecodeOptions {
+                cancel: self.cancel.clone(),
+                ..self.decode_options.clone()
+            },
+            work,
+            submitted: Instant::now(),
+        })));
+    }
+}
+
+/// The model a request asked for, or the configured one.
+fn requested_model(requested: Option<&str>, config: &SttConfig) -> String {
+    match requested {
+        Some(name) => resolve_model_path(name, &crate::home_dir()),
+        None => config.model_path.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioConfig, Channel, DeviceInfo, DeviceLister, Downmix};
+    use crate::events;
+    use crate::model_pool::{ModelPool, ModelPoolConfig};
+    use crate::profile::OutputMode;
+    use crate::socket::StartOptions;
+    use ringbuf::HeapProducer;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use tokio::time::timeout;
+
+    /// The sound card as a test sees it.
+    #[derive(Default)]
+    struct FakeInput {
+        /// The open end of the stream; `None` while the daemon has it closed
+        stream: Option<(HeapProducer<f32>, StreamSignals)>,
+        /// Plugged-in devices; the first one is the default
+        devices: Vec<String>,
+        /// Opening any device fails
+        broken: bool,
+    }
+
+    type FakeStream = Rc<RefCell<FakeInput>>;
+
+    /// Stands in for the sound card: tests write samples through the shared stream.
+    struct FakeSource(FakeStream);
+
+    impl AudioSource for FakeSource {
+        fn open(
+            &mut self,
+            device: &str,
+            channel: Channel,
+            producer: HeapProducer<f32>,
+            signals: StreamSignals,
+        ) -> Result<(String, u32)> {
+            let mut input = self.0.borrow_mut();
+            if input.broken {
+                anyhow::bail!("Device busy");
+            }
+            // Every fake device is stereo
+            Downmix::new(channel, 2)?;
+            let name = if device.is_empty() {
+                input.devices.first().map(String::as_str)
+            } else {
+                match_device(&input.devices, device)
+            }
+            .ok_or_else(|| UnknownDevice(device.to_string()))?
+            .to_string();
+            input.stream = Some((producer, signals));
+            Ok((name, 16000))
+        }
+
+        fn close(&mut self) {
+            self.0.borrow_mut().stream = None;
+        }
+
+        fn device_lister(&self) -> DeviceLister {
+            let names = self.0.borrow().devices.clone();
+            Box::new(move || {
+                Ok(names
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, name)| DeviceInfo {
+                        name,
+                        default: i == 0,
+                        ..DeviceInfo::default()
+                    })
+                    .collect())
+            })
+        }
+    }
+
+    /// A daemon on the mock engine, fed by the returned stream instead of a device.
+    fn daemon(audio: AudioConfig) -> (Daemon, FakeStream) {
+        daemon_with(SttConfig {
+            audio,
+            ..SttConfig::default()
+        })
+    }
+
+    fn daemon_with(config: SttConfig) -> (Daemon, FakeStream) {
+        let config = SttConfig {
+            engine: "mock".to_string(),
+            ..config
+        };
+        let events = events::channel();
+        let pool = ModelPool::new(&config.engine, &ModelPoolConfig::default());
+        let (worker, results_rx) = Worker::spawn(pool, events.clone()).unwrap();
+        let stream = FakeStream::new(RefCell::new(FakeInput {
+            stream: None,
+            devices: vec!["Built-in Audio".to_string(), "USB Microphone".to_string()],
+            broken: false,
+        }));
+        let source = Box::new(FakeSource(Rc::clone(&stream)));
+        let daemon =
+            Daemon::new(config.clone(), config, worker, results_rx, source, events).unwrap();
+        (daemon, stream)
+    }
+
+    fn feed(stream: &FakeStream, samples: usize) {
+        feed_samples(stream, &vec![0.1; samples]);
+    }
+
+    fn feed_samples(stream: &FakeStream, samples: &[f32]) {
+        let mut input = stream.borrow_mut();
+        let (producer, signals) = input.stream.as_mut().expect("the microphone is closed");
+        producer.push_slice(samples);
+        signals.ready.notify_one();
+    }
+
+    fn captured(daemon: &Daemon) -> usize {
+        daemon.consumer.as_ref().map_or(0, |c| c.len())
+    }
+
+    /// Starts a recording and waits until its model has loaded.
+    async fn start(daemon: &mut Daemon) {
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        let (response_tx, mut response_rx) = oneshot::channel();
+        cmd_tx
+            .send(Command::Start {
+                options: StartOptions::default(),
+                response_tx,
+            })
+            .await
+            .unwrap();
+        while response_rx.try_recv().is_err() {
+            assert!(daemon.step(&mut cmd_rx).await);
+        }
+        assert_eq!(daemon.state, State::Recording);
+    }
+
+    #[tokio::test]
+    async fn idle_daemon_ignores_audio() {
+        let (mut daemon, stream) = daemon(AudioConfig {
+            idle_timeout_ms: 0,
+            preroll_ms: 0,
+            ..AudioConfig::default()
+        });
+        let (_cmd_tx, mut cmd_rx) = mpsc::channel(1);
+
+        feed(&stream, 4 * CHUNK_SIZE);
+        let step = timeout(Duration::from_millis(50), daemon.step(&mut cmd_rx)).await;
+        assert!(step.is_err(), "an idle daemon should keep waiting");
+        assert_eq!(captured(&daemon), 4 * CHUNK_SIZE);
+    }
+
+    #[tokio::test]
+    async fn idle_daemon_keeps_the_preroll_without_waking() {
+        let (mut daemon, stream) = daemon(AudioConfig::default());
+        let (_cmd_tx, mut cmd_rx) = mpsc::channel(1);
+
+        feed(&stream, 4 * CHUNK_SIZE);
+        let step = timeout(Duration::from_millis(50), daemon.step(&mut cmd_rx)).await;
+        assert!(step.is_err(), "an idle daemon should keep waiting");
+        assert_eq!(captured(&daemon), 4 * CHUNK_SIZE);
+    }
+
+    #[tokio::test]
+    async fn recording_wakes_on_audio() {
+        let (mut daemon, stream) = daemon(AudioConfig::default());
+        let (_cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        start(&mut daemon).await;
+
+        feed(&stream, 4 * CHUNK_SIZE);
+        let step = timeout(Duration::from_millis(500), daemon.step(&mut cmd_rx)).await;
+        assert_eq!(step.ok(), Some(true));
+        assert_eq!(captured(&daemon), 0);
+        assert_eq!(daemon.audio_buffer.len(), 4 * CHUNK_SIZE);
+    }
+
+    #[tokio::test]
+    async fn idle_microphone_closes_and_reopens_with_preroll() {
+        let (mut daemon, stream) = daemon(AudioConfig {
+            idle_timeout_ms: 20,
+            preroll_ms: 100,
+            ..AudioConfig::default()
+        });
+        let (_cmd_tx, mut cmd_rx) = mpsc::channel(1);
+
+        // Only the last 100 ms survive while idle
+        feed(&stream, 16000);
+        daemon.trim_at = time::Instant::now();
+        assert!(daemon.step(&mut cmd_rx).await);
+        assert_eq!(captured(&daemon), 1600);
+
+        let step = timeout(Duration::from_millis(500), daemon.step(&mut cmd_rx)).await;
+        assert_eq!(step.ok(), Some(true));
+        assert!(stream.borrow().stream.is_none());
+        assert_eq!(daemon.status().audio_stream, "closed");
+
+        start(&mut daemon).await;
+        assert!(stream.borrow().stream.is_some());
+        assert_eq!(daemon.status().audio_stream, "open");
+    }
+
+    #[tokio::test]
+    async fn lost_device_falls_back_to_default() {
+        let (mut daemon, stream) = daemon(AudioConfig {
+            device: "usb".to_string(),
+            idle_timeout_ms: 0,
+            preroll_ms: 0,
+            ..AudioConfig::default()
+        });
+        let (_cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        assert_eq!(daemon.status().audio_device, "USB Microphone");
+
+        // Unplugged
+        let failed = {
+            let mut input = stream.borrow_mut();
+            input.devices.pop();
+            input.stream.as_ref().unwrap().1.failed.clone()
+        };
+        failed.notify_one();
+        let step = timeout(Duration::from_millis(500), daemon.step(&mut cmd_rx)).await;
+        assert_eq!(step.ok(), Some(true));
+        assert_eq!(daemon.status().audio_device, "Built-in Audio");
+
+        let err = daemon.set_device("webcam".to_string()).unwrap_err();
+        assert!(err.is::<UnknownDevice>());
+    }
+
+    #[tokio::test]
+    async fn silence_around_speech_is_trimmed() {
+        let (mut daemon, stream) = daemon(AudioConfig {
+            preroll_ms: 0,
+            ..AudioConfig::default()
+        });
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        start(&mut daemon).await;
+
+        let mut audio = vec![0.0; 16000];
+        audio.extend(vad::corpus::speech(0.1, 1.0));
+        audio.extend(vec![0.0; 16000]);
+        feed_samples(&stream, &audio);
+        assert!(daemon.step(&mut cmd_rx).await);
+
+        let (response_tx, response_rx) = oneshot::channel();
+        cmd_tx
+            .send(Command::Stop {
+                model: None,
+                response_tx,
+            })
+            .await
+            .unwrap();
+        assert!(daemon.step(&mut cmd_rx).await);
+        assert!(daemon.step(&mut cmd_rx).await);
+        let result = response_rx.await.unwrap().unwrap();
+
+        // One second of speech and 300 ms of padding on each side
+        assert!(
+            result.duration_ms.abs_diff(1600) <= 100,
+            "{}",
+            result.duration_ms
+        );
+        // Timestamps still count from the start of the recording
+        let segment = &result.transcript.segments[0];
+        assert!(
+            segment.start_ms.abs_diff(1000) <= 100,
+            "{}",
+            segment.start_ms
+        );
+    }
+
+    /// A recording set to stop after 500 ms of silence, fed `speech_ms` of speech and
+    /// then a second of silence.
+    async fn auto_stop_after(speech_ms: usize) -> (Daemon, Vec<Event>) {
+        let (mut daemon, stream) = daemon_with(SttConfig {
+            auto_stop_silence_ms: 500,
+            auto_stop_min_speech_ms: 300,
+            audio: AudioConfig {
+                preroll_ms: 0,
+                ..AudioConfig::default()
+            },
+            ..SttConfig::default()
+        });
+        let mut events = daemon.events.subscribe();
+        let (_cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        start(&mut daemon).await;
+
+        let mut audio = vad::corpus::speech(0.1, speech_ms as f32 / 1000.0);
+        audio.extend(vec![0.0; 16000]);
+        feed_samples(&stream, &audio);
+        assert!(daemon.step(&mut cmd_rx).await);
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        (daemon, received)
+    }
+
+    #[tokio::test]
+    async fn silence_after_speech_stops_the_recording() {
+        let (daemon, events) = auto_stop_after(1000).await;
+        assert_eq!(daemon.state, State::Processing);
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, Event::AutoStop { reason } if reason == "silence"))
+        );
+        // Stopped half a second into the silence, not at the end of the audio
+        let silence = daemon.vad.trailing_silence().unwrap() / SAMPLES_PER_MS;
+        assert!((500..600).contains(&silence), "{}", silence);
+    }
+
+    #[tokio::test]
+    async fn a_cough_does_not_stop_the_recording() {
+        let (daemon, events) = auto_stop_after(200).await;
+        assert_eq!(daemon.state, State::Recording);
+        assert!(!events.iter().any(|e| matches!(e, Event::AutoStop { .. })));
+    }
+
+    #[tokio::test]
+    async fn failed_model_load_abandons_the_recording() {
+        let (mut daemon, _stream) = daemon(AudioConfig::default());
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        let (response_tx, response_rx) = oneshot::channel();
+        cmd_tx
+            .send(Command::Start {
+                options: StartOptions {
+                    model: Some("/nowhere/missing.bin".to_string()),
+                    ..StartOptions::default()
+                },
+                response_tx,
+            })
+            .await
+            .unwrap();
+        assert!(daemon.step(&mut cmd_rx).await);
+        assert_eq!(daemon.state, State::Recording);
+
+        // The load result comes back from the worker
+        assert!(daemon.step(&mut cmd_rx).await);
+        let err = response_rx.await.unwrap().unwrap_err();
+        assert!(
+            matches!(&err, StartError::ModelLoad(message) if message.contains("missing.bin")),
+            "{:?}",
+            err
+        );
+        assert_eq!(daemon.state, State::Idle);
+        assert!(daemon.start_tx.is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_reload_keeps_the_current_config() {
+        let (mut daemon, _stream) = daemon(AudioConfig::default());
+        let mut events = daemon.events.subscribe();
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        let mut new_config = daemon.base_config.clone();
+        new_config.model_path = "/nowhere/missing.bin".to_string();
+        new_config.output_mode = OutputMode::Copy;
+
+        let (response_tx, response_rx) = oneshot::channel();
+        cmd_tx
+            .send(Command::ReloadConfig {
+                new_config: Box::new(new_config),
+                response_tx,
+            })
+            .await
+            .unwrap();
+        assert!(daemon.step(&mut cmd_rx).await);
+        // Nothing changes while the model loads
+        assert_eq!(daemon.config.output_mode, OutputMode::Type);
+
+        assert!(daemon.step(&mut cmd_rx).await);
+        let err = response_rx.await.unwrap().unwrap_err();
+        assert!(format!("{:#}", err).contains("missing.bin"), "{:#}", err);
+        assert_eq!(daemon.config.output_mode, OutputMode::Type);
+        assert_eq!(daemon.base_config.output_mode, OutputMode::Type);
+        assert!(daemon.pending_configs.is_empty());
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(event, Event::ProfileChanged { .. }));
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_channel_is_reported() {
+        let (mut daemon, _stream) = daemon(AudioConfig {
+            idle_timeout_ms: 0,
+            ..AudioConfig::default()
+        });
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        let mut new_config = daemon.base_config.clone();
+        new_config.audio.device = "usb".to_string();
+        new_config.audio.channel = Channel::Index(2);
+
+        let (response_tx, response_rx) = oneshot::channel();
+        cmd_tx
+            .send(Command::ReloadConfig {
+                new_config: Box::new(new_config),
+                response_tx,
+            })
+            .await
+            .unwrap();
+        assert!(daemon.step(&mut cmd_rx).await);
+        assert!(daemon.step(&mut cmd_rx).await);
+        let err = response_rx.await.unwrap().unwrap_err();
+        assert!(err.is::<NoSuchChannel>(), "{:#}", err);
+        // Not quietly swapped for the default device either
+        assert!(daemon.consumer.is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_device_switch_keeps_reconnecting() {
+        let (mut daemon, stream) = daemon(AudioConfig {
+            idle_timeout_ms: 0,
+            ..AudioConfig::default()
+        });
+        stream.borrow_mut().broken = true;
+
+        assert!(daemon.set_device("usb".to_string()).is_err());
+        assert!(daemon.consumer.is_none());
+        assert!(daemon.reconnect_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn lists_devices_with_the_active_one() {
+        let (mut daemon, _stream) = daemon(AudioConfig::default());
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        let (response_tx, response_rx) = oneshot::channel();
+        cmd_tx
+            .send(Command::ListDevices { response_tx })
+            .await
+            .unwrap();
+        assert!(daemon.step(&mut cmd_rx).await);
+
+        let devices = response_rx.await.unwrap().unwrap();
+        let active: Vec<&str> = devices
+            .iter()
+            .filter(|d| d.active)
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(active, vec!["Built-in Audio"]);
+    }
+}
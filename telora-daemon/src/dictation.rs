@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+
+use crate::vad::Vad;
+
+const SAMPLE_RATE: u32 = 16000;
+// Audio kept from before speech onset so the first syllable is not clipped
+const PREROLL_MS: usize = 300;
+
+/// Cuts a continuous 16 kHz stream into utterances using the VAD.
+pub struct Dictation {
+    vad: Vad,
+    preroll: VecDeque<f32>,
+    preroll_samples: usize,
+    utterance: Vec<f32>,
+    max_samples: usize,
+}
+
+impl Dictation {
+    pub fn new(vad: Vad, max_seconds: u32) -> Self {
+        let preroll_samples = PREROLL_MS * SAMPLE_RATE as usize / 1000;
+        Self {
+            vad,
+            preroll: VecDeque::with_capacity(preroll_samples),
+            preroll_samples,
+            utterance: Vec::new(),
+            max_samples: max_seconds as usize * SAMPLE_RATE as usize,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.vad.reset();
+        self.preroll.clear();
+        self.utterance.clear();
+    }
+
+    /// Feeds audio and returns a finished utterance once speech ends.
+    pub fn push(&mut self, samples: &[f32]) -> Option<Vec<f32>> {
+        let speaking = self.vad.is_voice_segment(samples);
+
+        if speaking {
+            if self.utterance.is_empty() {
+                self.utterance.extend(self.preroll.drain(..));
+            }
+            self.utterance.extend_from_slice(samples);
+
+            // Very long utterances are cut so they can still be transcribed; the
+            // speech that follows starts the next one
+            if self.utterance.len() >= self.max_samples {
+                return Some(std::mem::take(&mut self.utterance));
+            }
+            return None;
+        }
+
+        if !self.utterance.is_empty() {
+            // Include the trailing hangover that closed the utterance
+            self.utterance.extend_from_slice(samples);
+            return Some(std::mem::take(&mut self.utterance));
+        }
+
+        self.preroll.extend(samples);
+        let excess = self.preroll.len().saturating_sub(self.preroll_samples);
+        self.preroll.drain(..excess);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vad::{VadConfig, corpus};
+
+    const CHUNK: usize = 512;
+    const HANGOVER_MS: u32 = 300;
+
+    fn dictation(max_seconds: u32) -> Dictation {
+        let vad = Vad::new(&VadConfig {
+            hangover_ms: HANGOVER_MS,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        Dictation::new(vad, max_seconds)
+    }
+
+    /// Feeds the audio in chunks; returns each utterance with the number of samples
+    /// fed when it came out.
+    fn utterances(dictation: &mut Dictation, audio: &[f32]) -> Vec<(usize, Vec<f32>)> {
+        let mut out = Vec::new();
+        for (i, chunk) in audio.chunks(CHUNK).enumerate() {
+            if let Some(utterance) = dictation.push(chunk) {
+                out.push((i * CHUNK + chunk.len(), utterance));
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn utterance_ends_after_the_hangover() {
+        let mut audio = vec![0.0; 16000];
+        audio.extend(corpus::speech(0.1, 1.0));
+        audio.extend(vec![0.0; 24000]);
+
+        let out = utterances(&mut dictation(30), &audio);
+        assert_eq!(out.len(), 1);
+        let (fed, utterance) = &out[0];
+
+        // Cut once the hangover has passed after the end of the speech
+        let after_speech = fed - 32000;
+        let hangover = HANGOVER_MS as usize * 16;
+        assert!(
+            after_speech.abs_diff(hangover) <= 2 * CHUNK,
+            "{} samples after speech",
+            after_speech
+        );
+        // Speech and hangover, plus the pre-roll
+        assert!(utterance.len() > 16000 + after_speech);
+    }
+
+    #[test]
+    fn preroll_keeps_the_audio_before_onset() {
+        let mut audio = corpus::white(0.001, 1.0, 7);
+        audio.extend(corpus::speech(0.1, 1.0));
+        audio.extend(vec![0.0; 24000]);
+
+        let out = utterances(&mut dictation(30), &audio);
+        let (fed, utterance) = &out[0];
+        let start = fed - utterance.len();
+        assert_eq!(utterance[..], audio[start..*fed]);
+
+        // Onset is confirmed a few frames into the speech; the 300 ms kept from before
+        // that still reach back past its start
+        let preroll = PREROLL_MS * 16;
+        assert!(
+            (16000 - preroll..=16000).contains(&start),
+            "starts at {}",
+            start
+        );
+    }
+
+    #[test]
+    fn long_speech_is_split_at_the_limit() {
+        let audio = corpus::speech(0.1, 2.5);
+
+        let out = utterances(&mut dictation(1), &audio);
+        assert!(out.len() >= 2, "{} utterances", out.len());
+        for (_, utterance) in &out[..2] {
+            assert!(
+                (16000..16000 + CHUNK).contains(&utterance.len()),
+                "{} samples",
+                utterance.len()
+            );
+        }
+    }
+}
@@ -0,0 +1,632 @@
+use anyhow::{Result, anyhow};
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::resample::TARGET_SAMPLE_RATE;
+
+const SAMPLE_RATE: f32 = TARGET_SAMPLE_RATE as f32;
+
+/// Settings for the `[dsp]` config table. Every stage is off by default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DspConfig {
+    /// Input gain in dB
+    pub gain_db: f32,
+    /// High-pass cutoff in Hz, which also removes any DC offset; 0 disables it
+    pub highpass_hz: f32,
+    /// Spectral subtraction of steady background noise such as fans or hum
+    pub noise_suppression: bool,
+    /// Mute audio that stays below this level, in dBFS; 0 disables the gate
+    pub gate_threshold_db: f32,
+    /// How long the gate stays open once the level drops, in milliseconds
+    pub gate_hold_ms: u32,
+    pub normalize: Normalize,
+    /// Level `normalize` aims for, in dBFS; -3 for peak and -20 for loudness if unset
+    pub normalize_target_db: Option<f32>,
+    /// Most `normalize` may amplify, in dB, so near silence is not pumped up
+    pub normalize_max_gain_db: f32,
+}
+
+impl Default for DspConfig {
+    fn default() -> Self {
+        Self {
+            gain_db: 0.0,
+            highpass_hz: 0.0,
+            noise_suppression: false,
+            gate_threshold_db: 0.0,
+            gate_hold_ms: 200,
+            normalize: Normalize::Off,
+            normalize_target_db: None,
+            normalize_max_gain_db: 20.0,
+        }
+    }
+}
+
+/// Level normalization, adapting the gain as the speaker gets louder or quieter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalize {
+    #[default]
+    Off,
+    /// Bring peaks to the target
+    Peak,
+    /// Bring the RMS level to the target
+    Loudness,
+}
+
+impl DspConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(-40.0..=40.0).contains(&self.gain_db) {
+            return Err(anyhow!(
+                "gain_db must be between -40 and 40, got {}",
+                self.gain_db
+            ));
+        }
+        if !(0.0..=1000.0).contains(&self.highpass_hz) {
+            return Err(anyhow!(
+                "highpass_hz must be between 0 and 1000, got {}",
+                self.highpass_hz
+            ));
+        }
+        if self.gate_threshold_db > 0.0 {
+            return Err(anyhow!(
+                "gate_threshold_db must be at most 0, got {}",
+                self.gate_threshold_db
+            ));
+        }
+        if let Some(target) = self.normalize_target_db
+            && !(-60.0..=0.0).contains(&target)
+        {
+            return Err(anyhow!(
+                "normalize_target_db must be between -60 and 0, got {}",
+                target
+            ));
+        }
+        if !(0.0..=60.0).contains(&self.normalize_max_gain_db) {
+            return Err(anyhow!(
+                "normalize_max_gain_db must be between 0 and 60, got {}",
+                self.normalize_max_gain_db
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Coefficient of a one-pole smoother with the given time constant.
+fn smoothing(ms: f32) -> f32 {
+    (-1000.0 / (ms * SAMPLE_RATE)).exp()
+}
+
+/// The configured stages, run on 16 kHz audio in this order: gain, high-pass, noise
+/// suppression, gate, normalization. Filter state carries over between calls.
+pub struct Dsp {
+    gain: f32,
+    highpass: Option<HighPass>,
+    suppressor: Option<NoiseSuppressor>,
+    gate: Option<NoiseGate>,
+    normalizer: Option<Normalizer>,
+}
+
+impl Dsp {
+    pub fn new(config: &DspConfig) -> Result<Self> {
+        config.validate()?;
+
+        let normalizer = match config.normalize {
+            Normalize::Off => None,
+            mode => Some(Normalizer::new(
+                mode,
+                config.normalize_target_db,
+                config.normalize_max_gain_db,
+            )),
+        };
+        Ok(Self {
+            gain: db_to_linear(config.gain_db),
+            highpass: (config.highpass_hz > 0.0).then(|| HighPass::new(config.highpass_hz)),
+            suppressor: config.noise_suppression.then(NoiseSuppressor::new),
+            gate: (config.gate_threshold_db < 0.0)
+                .then(|| NoiseGate::new(config.gate_threshold_db, config.gate_hold_ms)),
+            normalizer,
+        })
+    }
+
+    /// Clears filter state so the next recording starts clean.
+    pub fn reset(&mut self) {
+        if let Some(highpass) = &mut self.highpass {
+            highpass.reset();
+        }
+        if let Some(suppressor) = &mut self.suppressor {
+            suppressor.reset();
+        }
+        if let Some(gate) = &mut self.gate {
+            gate.reset();
+        }
+        if let Some(normalizer) = &mut self.normalizer {
+            normalizer.reset();
+        }
+    }
+
+    /// Processes a block. Noise suppression holds back a few milliseconds, so the
+    /// output can be shorter than the input until `flush`.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        let mut samples: Vec<f32> = samples.iter().map(|s| s * self.gain).collect();
+        if let Some(highpass) = &mut self.highpass {
+            highpass.process(&mut samples);
+        }
+        if let Some(suppressor) = &mut self.suppressor {
+            samples = suppressor.process(&samples);
+        }
+        self.finish(&mut samples);
+        samples
+    }
+
+    /// Returns the audio still held back at the end of a recording.
+    pub fn flush(&mut self) -> Vec<f32> {
+        let mut samples = match &mut self.suppressor {
+            Some(suppressor) => suppressor.flush(),
+            None => Vec::new(),
+        };
+        self.finish(&mut samples);
+        samples
+    }
+
+    fn finish(&mut self, samples: &mut [f32]) {
+        if let Some(gate) = &mut self.gate {
+            gate.process(samples);
+        }
+        if let Some(normalizer) = &mut self.normalizer {
+            normalizer.process(samples);
+        }
+    }
+}
+
+/// Second-order Butterworth high-pass (RBJ cookbook biquad).
+struct HighPass {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl HighPass {
+    fn new(cutoff_hz: f32) -> Self {
+        let w0 = 2.0 * std::f32::consts::PI * cutoff_hz / SAMPLE_RATE;
+        let alpha = w0.sin() / (2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+        Self {
+            b: [
+                (1.0 + cos) / 2.0 / a0,
+                -(1.0 + cos) / a0,
+                (1.0 + cos) / 2.0 / a0,
+            ],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.x = [0.0; 2];
+        self.y = [0.0; 2];
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for s in samples {
+            let x = *s;
+            let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+                - self.a[0] * self.y[0]
+                - self.a[1] * self.y[1];
+            self.x = [x, self.x[0]];
+            self.y = [y, self.y[0]];
+            *s = y;
+        }
+    }
+}
+
+/// Mutes the signal while its envelope stays under the threshold.
+struct NoiseGate {
+    threshold: f32,
+    hold: usize,
+    envelope: f32,
+    /// Samples left before the gate starts closing
+    held: usize,
+    gain: f32,
+}
+
+impl NoiseGate {
+    fn new(threshold_db: f32, hold_ms: u32) -> Self {
+        Self {
+            threshold: db_to_linear(threshold_db),
+            hold: (hold_ms as f32 * SAMPLE_RATE / 1000.0) as usize,
+            envelope: 0.0,
+            held: 0,
+            gain: 0.0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.held = 0;
+        self.gain = 0.0;
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        let envelope_decay = smoothing(10.0);
+        // Opens within a millisecond so onsets survive; closes gently
+        let open = smoothing(1.0);
+        let close = smoothing(20.0);
+
+        for s in samples {
+            self.envelope = s.abs().max(self.envelope * envelope_decay);
+            if self.envelope >= self.threshold {
+                self.held = self.hold;
+            } else {
+                self.held = self.held.saturating_sub(1);
+            }
+
+            let (target, coeff) = if self.held > 0 {
+                (1.0, open)
+            } else {
+                (0.0, close)
+            };
+            self.gain = target + (self.gain - target) * coeff;
+            *s *= self.gain;
+        }
+    }
+}
+
+/// Slow automatic gain towards a peak or RMS target.
+struct Normalizer {
+    mode: Normalize,
+    target: f32,
+    max_gain: f32,
+    /// Peak or mean square, depending on the mode
+    level: f32,
+    gain: f32,
+}
+
+impl Normalizer {
+    fn new(mode: Normalize, target_db: Option<f32>, max_gain_db: f32) -> Self {
+        let target_db = target_db.unwrap_or(match mode {
+            Normalize::Loudness => -20.0,
+            _ => -3.0,
+        });
+        Self {
+            mode,
+            target: db_to_linear(target_db),
+            max_gain: db_to_linear(max_gain_db),
+            level: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.level = 0.0;
+        self.gain = 1.0;
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        let peak_decay = smoothing(1000.0);
+        let loudness_window = smoothing(400.0);
+        // Backs off quickly to avoid clipping, rises slowly to avoid pumping
+        let attack = smoothing(5.0);
+        let release = smoothing(500.0);
+
+        for s in samples {
+            let level = match self.mode {
+                Normalize::Loudness => {
+                    let square = *s * *s;
+                    self.level = square + (self.level - square) * loudness_window;
+                    self.level.sqrt()
+                }
+                _ => {
+                    self.level = s.abs().max(self.level * peak_decay);
+                    self.level
+                }
+            };
+
+            let wanted = if level > 0.0 {
+                (self.target / level).min(self.max_gain)
+            } else {
+                self.max_gain
+            };
+            let coeff = if wanted < self.gain { attack } else { release };
+            self.gain = wanted + (self.gain - wanted) * coeff;
+            *s = (*s * self.gain).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+// STFT frame and hop of the noise suppressor: 32 ms frames, 50% overlap
+const FRAME: usize = 512;
+const HOP: usize = FRAME / 2;
+// Per-frame growth allowed to the noise estimate, about 10 dB in two seconds
+const NOISE_RISE: f32 = 1.02;
+// Over-subtraction, and the least a bin is attenuated to, as a power ratio
+const OVER_SUBTRACTION: f32 = 2.0;
+const GAIN_FLOOR: f32 = 0.01;
+// The tracked minimum sits below the average noise power by about this factor
+const MINIMUM_BIAS: f32 = 2.0;
+
+/// Spectral subtraction with a minimum-tracking noise estimate.
+///
+/// Speech comes and goes while steady noise does not, so the quietest recent power
+/// of each frequency bin approximates the noise there. Each frame is attenuated bin
+/// by bin according to how far it sits above that estimate.
+struct NoiseSuppressor {
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+    /// Square-root Hann, applied before and after, so overlapping frames add to one
+    window: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    frame: Vec<f32>,
+    /// Input not yet covered by a full frame
+    pending: Vec<f32>,
+    /// Overlap-add accumulator
+    output: Vec<f32>,
+    /// Smoothed power and noise estimate per bin
+    power: Vec<f32>,
+    noise: Vec<f32>,
+    /// Leading output that only covers the priming zeros
+    skip: usize,
+    frames_in: usize,
+    frames_out: usize,
+}
+
+impl NoiseSuppressor {
+    fn new() -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(FRAME);
+        let ifft = planner.plan_fft_inverse(FRAME);
+        let spectrum = fft.make_output_vec();
+        let bins = spectrum.len();
+        let window = (0..FRAME)
+            .map(|i| (std::f32::consts::PI * i as f32 / FRAME as f32).sin())
+            .collect();
+
+        let mut suppressor = Self {
+            fft,
+            ifft,
+            window,
+            spectrum,
+            frame: vec![0.0; FRAME],
+            pending: Vec::with_capacity(FRAME * 2),
+            output: vec![0.0; FRAME],
+            power: vec![0.0; bins],
+            noise: vec![f32::INFINITY; bins],
+            skip: 0,
+            frames_in: 0,
+            frames_out: 0,
+        };
+        suppressor.reset();
+        suppressor
+    }
+
+    fn reset(&mut self) {
+        // Primed with half a frame of silence so the first samples get a full overlap
+        self.pending.clear();
+        self.pending.resize(HOP, 0.0);
+        self.output.fill(0.0);
+        self.power.fill(0.0);
+        self.noise.fill(f32::INFINITY);
+        self.skip = HOP;
+        self.frames_in = 0;
+        self.frames_out = 0;
+    }
+
+    fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.pending.extend_from_slice(samples);
+        self.frames_in += samples.len();
+
+        let mut out = Vec::with_capacity(samples.len() + HOP);
+        let mut consumed = 0;
+        while self.pending.len() - consumed >= FRAME {
+            self.process_frame(consumed);
+            consumed += HOP;
+
+            let ready = &self.output[..HOP];
+            let skipped = self.skip.min(HOP);
+            self.skip -= skipped;
+            out.extend_from_slice(&ready[skipped..]);
+            self.output.copy_within(HOP.., 0);
+            self.output[FRAME - HOP..].fill(0.0);
+        }
+        self.pending.drain(..consumed);
+        self.frames_out += out.len();
+        out
+    }
+
+    /// Pushes silence through until every input sample has come out.
+    fn flush(&mut self) -> Vec<f32> {
+        let missing = self.frames_in - self.frames_out;
+        let mut out = Vec::with_capacity(missing);
+        while out.len() < missing {
+            out.extend(self.process(&[0.0; HOP]));
+        }
+        out.truncate(missing);
+        self.reset();
+        out
+    }
+
+    fn process_frame(&mut self, start: usize) {
+        for (i, slot) in self.frame.iter_mut().enumerate() {
+            *slot = self.pending[start + i] * self.window[i];
+        }
+        if self
+            .fft
+            .process(&mut self.frame, &mut self.spectrum)
+            .is_err()
+        {
+            return;
+        }
+
+        for (k, bin) in self.spectrum.iter_mut().enumerate() {
+            let power = bin.norm_sqr();
+            self.power[k] = 0.85 * self.power[k] + 0.15 * power;
+            self.noise[k] = self.power[k].min(self.noise[k] * NOISE_RISE);
+
+            let gain = (1.0 - OVER_SUBTRACTION * MINIMUM_BIAS * self.noise[k] / (power + 1e-12))
+                .max(GAIN_FLOOR);
+            *bin *= gain.sqrt();
+        }
+        // The inverse transform wants purely real DC and Nyquist bins
+        let last = self.spectrum.len() - 1;
+        self.spectrum[0].im = 0.0;
+        self.spectrum[last].im = 0.0;
+
+        if self
+            .ifft
+            .process(&mut self.spectrum, &mut self.frame)
+            .is_err()
+        {
+            return;
+        }
+        let scale = 1.0 / FRAME as f32;
+        for (i, sample) in self.frame.iter().enumerate() {
+            self.output[i] += sample * self.window[i] * scale;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vad::corpus;
+
+    fn sine(freq: f32, amplitude: f32, seconds: f32) -> Vec<f32> {
+        (0..(seconds * SAMPLE_RATE) as usize)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * freq * i as f32 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |p, s| p.max(s.abs()))
+    }
+
+    fn dsp(config: DspConfig) -> Dsp {
+        Dsp::new(&config).unwrap()
+    }
+
+    #[test]
+    fn default_chain_is_transparent() {
+        let input = sine(440.0, 0.5, 0.1);
+        assert_eq!(dsp(DspConfig::default()).process(&input), input);
+    }
+
+    #[test]
+    fn gain_scales_the_signal() {
+        let mut chain = dsp(DspConfig {
+            gain_db: 6.0,
+            ..DspConfig::default()
+        });
+        let output = chain.process(&[0.1, -0.2]);
+        assert!((output[0] - 0.1995).abs() < 1e-3);
+        assert!((output[1] + 0.399).abs() < 1e-3);
+    }
+
+    #[test]
+    fn highpass_removes_dc_and_keeps_speech() {
+        let mut chain = dsp(DspConfig {
+            highpass_hz: 80.0,
+            ..DspConfig::default()
+        });
+        let offset: Vec<f32> = sine(440.0, 0.3, 1.0).iter().map(|s| s + 0.2).collect();
+        let output = chain.process(&offset);
+
+        let settled = &output[8000..];
+        let mean = settled.iter().sum::<f32>() / settled.len() as f32;
+        assert!(mean.abs() < 0.005, "DC left: {}", mean);
+        assert!((peak(settled) - 0.3).abs() < 0.02);
+    }
+
+    #[test]
+    fn gate_mutes_background_and_passes_speech() {
+        let mut chain = dsp(DspConfig {
+            gate_threshold_db: -40.0,
+            ..DspConfig::default()
+        });
+        // -60 dBFS hiss, then a -10 dBFS tone
+        let hiss = chain.process(&corpus::white(0.001, 0.5, 12345));
+        assert!(peak(&hiss[1600..]) < 1e-5);
+
+        let tone = chain.process(&sine(440.0, 0.3, 0.5));
+        assert!((peak(&tone[800..]) - 0.3).abs() < 0.01);
+    }
+
+    #[test]
+    fn peak_normalization_reaches_the_target() {
+        let mut chain = dsp(DspConfig {
+            normalize: Normalize::Peak,
+            normalize_max_gain_db: 40.0,
+            ..DspConfig::default()
+        });
+        // A -30 dBFS speaker brought to -3 dBFS
+        let output = chain.process(&sine(440.0, 0.0316, 4.0));
+        let last = peak(&output[output.len() - 8000..]);
+        assert!((last - 0.708).abs() < 0.05, "peak {}", last);
+    }
+
+    #[test]
+    fn loudness_normalization_respects_max_gain() {
+        let mut chain = dsp(DspConfig {
+            normalize: Normalize::Loudness,
+            ..DspConfig::default()
+        });
+        let output = chain.process(&sine(440.0, 0.0316, 4.0));
+        let last = rms(&output[output.len() - 8000..]);
+        assert!((last - 0.1).abs() < 0.01, "rms {}", last);
+
+        // Near silence gets at most the 20 dB default
+        chain.reset();
+        let output = chain.process(&corpus::white(0.0001, 2.0, 12345));
+        assert!(peak(&output) <= 0.0011);
+    }
+
+    #[test]
+    fn suppression_reduces_steady_noise() {
+        let mut chain = dsp(DspConfig {
+            noise_suppression: true,
+            ..DspConfig::default()
+        });
+        let background = corpus::white(0.05, 3.0, 12345);
+        let tone = sine(440.0, 0.3, 1.0);
+        let mut input = background.clone();
+        input.extend(tone.iter().zip(&background).map(|(t, n)| t + n));
+
+        let mut output = chain.process(&input);
+        output.extend(chain.flush());
+        assert_eq!(output.len(), input.len());
+
+        // The noise estimate has settled by the last second of noise alone
+        let noise_in = rms(&input[32000..48000]);
+        let noise_out = rms(&output[32000..48000]);
+        assert!(noise_out < noise_in / 2.0, "{} -> {}", noise_in, noise_out);
+
+        // The tone stands well above the noise and survives
+        let tone_out = rms(&output[52000..]);
+        assert!(
+            (tone_out - rms(&tone)).abs() < 0.05,
+            "tone rms {}",
+            tone_out
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_settings() {
+        let config = DspConfig {
+            highpass_hz: 9000.0,
+            ..DspConfig::default()
+        };
+        assert!(Dsp::new(&config).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::profile::OutputMode;
+use crate::socket::TranscriptionResult;
+
+/// Events pushed to clients that called `subscribe`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    StateChanged {
+        state: String,
+    },
+    /// The daemon stopped a recording on its own; the client should call `stop`
+    AutoStop {
+        reason: String,
+    },
+    ModelReloaded {
+        model_path: String,
+    },
+    ProfileChanged {
+        profile: String,
+        output_mode: OutputMode,
+    },
+    Partial {
+        text: String,
+    },
+    Final {
+        /// Whether this came from hands-free dictation rather than a `stop` request
+        dictation: bool,
+        result: TranscriptionResult,
+    },
+    Error {
+        message: String,
+    },
+    AudioLevel {
+        rms: f32,
+        peak: f32,
+    },
+}
+
+pub type EventSender = broadcast::Sender<Event>;
+
+pub fn channel() -> EventSender {
+    // Slow subscribers skip ahead instead of blocking the main loop
+    let (tx, _) = broadcast::channel(256);
+    tx
+}
+
+/// Publishes an event; it is dropped when nobody is subscribed.
+pub fn emit(events: &EventSender, event: Event) {
+    let _ = events.send(event);
+}
@@ -1,31 +1,44 @@
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use config::{Config, File};
-use log::{error, info, warn};
-use ringbuf::HeapRb;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::UnixStream;
-use tokio::sync::{mpsc, oneshot};
-use tokio::time::{Duration, sleep};
+use log::{info, warn};
+use std::time::Instant;
+use tokio::sync::mpsc;
 
 mod audio;
+mod audio_file;
+mod daemon;
+mod dictation;
+mod dsp;
+mod events;
+mod mock_engine;
+mod model_pool;
+mod partial;
+mod postprocess;
+mod profile;
+mod resample;
+mod rpc;
 mod socket;
+mod subtitles;
 mod transcriber;
 mod vad;
+mod vocabulary;
+mod voice_commands;
+mod whisper_engine;
+mod worker;
 
 use audio::AudioEngine;
-use socket::{Command, SocketServer, StatusResponse, SttConfig};
-use transcriber::Transcriber;
+use daemon::Daemon;
+use model_pool::{ModelPool, resolve_model_path};
+use postprocess::PostProcessor;
+use socket::{SocketServer, StatusResponse, SttConfig, TranscriptionResult};
+use subtitles::ExportFormat;
+use transcriber::{DecodeOptions, Transcriber};
+use vocabulary::Vocabulary;
+use worker::Worker;
 
 // Config references
 const SOCKET_PATH: &str = "/tmp/telora-sock";
-const CONTROL_SOCKET: &str = "/tmp/telora-control.sock";
-
-async fn notify_client_auto_stop() {
-    if let Ok(mut stream) = UnixStream::connect(CONTROL_SOCKET).await {
-        let _ = stream.write_all(b"AUTO_STOP").await;
-    }
-}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Telora Daemon - Background transcription service", long_about = None)]
@@ -52,6 +65,10 @@ struct Args {
     /// Maximum recording time in seconds (overrides config)
     #[arg(long)]
     max_recording_seconds: Option<u32>,
+
+    /// Profile to start with (overrides config)
+    #[arg(short, long)]
+    profile: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -60,17 +77,38 @@ enum Commands {
     Status,
     /// Reload configuration and restart the model if needed
     Refresh,
+    /// List audio input devices and the formats they support
+    Devices,
+    /// Transcribe an audio file (WAV, FLAC or OGG/Vorbis) without the daemon
+    Transcribe {
+        /// Audio file to transcribe
+        file: std::path::PathBuf,
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        /// Write the result to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+        /// Extra prompt text, such as names that come up in the recording
+        #[arg(long)]
+        hint: Option<String>,
+    },
 }
 
-#[derive(PartialEq)]
-enum State {
-    Idle,
-    Recording,
-    Processing,
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    Text,
+    Json,
+    Srt,
+    Vtt,
+}
+
+fn home_dir() -> String {
+    std::env::var("HOME").unwrap_or_else(|_| "/root".to_string())
 }
 
 fn load_config(args: &Args) -> SttConfig {
-    let home = std::env::var("HOME").unwrap_or_else(|_| "/root".to_string());
+    let home = home_dir();
 
     // Load configuration from multiple sources in order of precedence (last one wins)
     let mut builder = Config::builder();
@@ -93,21 +131,15 @@ fn load_config(args: &Args) -> SttConfig {
 
     let config_res = builder.build();
     let mut stt_config: SttConfig = match config_res {
-        Ok(c) => c.try_deserialize().unwrap_or(SttConfig {
-            model_path: "ggml-base.bin".to_string(),
-            language: "es".to_string(),
-            max_recording_seconds: 600,
-        }),
+        Ok(c) => c.try_deserialize().unwrap_or_default(),
         Err(e) => {
             warn!("Configuration warning: {}. Using defaults.", e);
-            SttConfig {
-                model_path: "ggml-base.bin".to_string(),
-                language: "es".to_string(),
-                max_recording_seconds: 600,
-            }
+            SttConfig::default()
         }
     };
 
+    stt_config.migrate();
+
     // CLI args override
     if let Some(m) = &args.model {
         stt_config.model_path = m.clone();
@@ -118,114 +150,71 @@ fn load_config(args: &Args) -> SttConfig {
     if let Some(s) = args.max_recording_seconds {
         stt_config.max_recording_seconds = s;
     }
+    if let Some(p) = &args.profile {
+        stt_config.profile = p.clone();
+    }
+
+    if stt_config.vocabulary_file.is_empty() {
+        stt_config.vocabulary_file = format!("{}/.config/telora/vocabulary.txt", home);
+    }
 
-    // Attempt to resolve model path if it's just a filename
-    if !std::path::Path::new(&stt_config.model_path).exists() {
-        let filename = if stt_config.model_path.contains('/') {
-            // If it contains a slash but doesn't exist, we'll still try to see if it's just the end part
-            std::path::Path::new(&stt_config.model_path)
-                .file_name()
-                .and_then(|f| f.to_str())
-                .unwrap_or(&stt_config.model_path)
-        } else {
-            &stt_config.model_path
-        };
-
-        let candidates = vec![
-            format!("{}/.local/share/telora/models/{}", home, filename),
-            format!("/usr/share/telora/models/{}", filename),
-            format!("models/{}", filename),
-            filename.to_string(),
-        ];
-
-        for path in candidates {
-            if std::path::Path::new(&path).exists() {
-                stt_config.model_path = path;
-                break;
-            }
+    stt_config.model_path = resolve_model_path(&stt_config.model_path, &home);
+    for profile in stt_config.profiles.values_mut() {
+        if let Some(model_path) = &mut profile.model_path {
+            *model_path = resolve_model_path(model_path, &home);
         }
     }
+    for model_path in &mut stt_config.model_pool.preload {
+        *model_path = resolve_model_path(model_path, &home);
+    }
 
     stt_config
 }
 
 async fn run_refresh_client(config: SttConfig) -> Result<()> {
-    let mut stream = match UnixStream::connect(SOCKET_PATH).await {
-        Ok(s) => s,
-        Err(_) => {
-            eprintln!("Error: Daemon is not running.");
-            return Ok(());
-        }
-    };
-
-    let config_json = serde_json::to_string(&config)?;
-    let command = format!("REFRESH {}", config_json);
-
-    if let Err(e) = stream.write_all(command.as_bytes()).await {
-        eprintln!("Failed to send refresh command to daemon: {}", e);
+    let Ok(mut client) = rpc::Client::connect(SOCKET_PATH).await else {
+        eprintln!("Error: Daemon is not running.");
         return Ok(());
-    }
+    };
 
-    let mut buf = Vec::new();
-    if let Err(e) = stream.read_to_end(&mut buf).await {
-        eprintln!("Failed to read response from daemon: {}", e);
-        return Ok(());
+    match client.call("refresh", serde_json::to_value(&config)?).await {
+        Ok(_) => println!("OK: Config reloaded"),
+        Err(e) => eprintln!("Failed to refresh daemon: {}", e),
     }
 
-    let response = String::from_utf8_lossy(&buf);
-    println!("{}", response);
-
     Ok(())
 }
 
 async fn run_status_client() -> Result<()> {
-    let mut stream = match UnixStream::connect(SOCKET_PATH).await {
-        Ok(s) => s,
-        Err(_) => {
-            println!("Telora Daemon Status");
-            println!(
-                "{:<10} {:<10} {:<30} {:<10} {:<10} {:<15}",
-                "ACTIVE", "PID", "MODEL", "LANG", "MAX_SEC", "STATE"
-            );
-            println!(
-                "{:-<10} {:-<10} {:-<30} {:-<10} {:-<10} {:-<15}",
-                "", "", "", "", "", ""
-            );
-            println!(
-                "{:<10} {:<10} {:<30} {:<10} {:<10} {:<15}",
-                "NO", "-", "-", "-", "-", "STOPPED"
-            );
+    let Ok(mut client) = rpc::Client::connect(SOCKET_PATH).await else {
+        println!("Telora Daemon Status");
+        println!(
+            "{:<10} {:<10} {:<30} {:<10} {:<10} {:<15}",
+            "ACTIVE", "PID", "MODEL", "LANG", "MAX_SEC", "STATE"
+        );
+        println!(
+            "{:-<10} {:-<10} {:-<30} {:-<10} {:-<10} {:-<15}",
+            "", "", "", "", "", ""
+        );
+        println!(
+            "{:<10} {:<10} {:<30} {:<10} {:<10} {:<15}",
+            "NO", "-", "-", "-", "-", "STOPPED"
+        );
+        return Ok(());
+    };
+
+    let response = match client.call("status", serde_json::Value::Null).await {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Daemon returned error: {}", e);
             return Ok(());
         }
     };
 
-    if let Err(e) = stream.write_all(b"STATUS").await {
-        eprintln!("Failed to send command to daemon: {}", e);
-        return Ok(());
-    }
-
-    let mut buf = Vec::new();
-    if let Err(e) = stream.read_to_end(&mut buf).await {
-        eprintln!("Failed to read response from daemon: {}", e);
-        return Ok(());
-    }
-
-    let response = String::from_utf8_lossy(&buf);
-
-    if response.trim().is_empty() {
-        eprintln!("Empty response from daemon.");
-        return Ok(());
-    }
-
-    if response.starts_with("ERROR") {
-        eprintln!("Daemon returned error: {}", response);
-        return Ok(());
-    }
-
-    let status: StatusResponse = match serde_json::from_str(&response) {
+    let status: StatusResponse = match serde_json::from_value(response) {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("Failed to parse response: {} (Response: {})", e, response);
+            eprintln!("Failed to parse response: {}", e);
             return Ok(());
         }
     };
@@ -263,8 +252,133 @@ async fn run_status_client() -> Result<()> {
         println!("\nFull Model Path: {}", status.model_path);
     }
 
+    if !status.engine.is_empty() {
+        let mut features = Vec::new();
+        if status.capabilities.language_detection {
+            features.push("language detection");
+        }
+        if status.capabilities.token_probabilities {
+            features.push("token probabilities");
+        }
+        println!(
+            "Engine: {} on {} ({})",
+            status.engine,
+            status.backend,
+            features.join(", ")
+        );
+    }
+
+    if status.vocabulary_terms > 0 {
+        println!(
+            "Vocabulary: {} ({} terms)",
+            status.vocabulary_path, status.vocabulary_terms
+        );
+    }
+
+    if !status.audio_device.is_empty() {
+        println!(
+            "Microphone: {} ({})",
+            status.audio_stream, status.audio_device
+        );
+    } else if !status.audio_stream.is_empty() {
+        println!("Microphone: {}", status.audio_stream);
+    }
+
+    if status.loaded_models.len() > 1 {
+        println!("Loaded Models: {}", status.loaded_models.join(", "));
+    }
+
+    if !status.profile.is_empty() {
+        println!(
+            "Profile: {} (output: {}; available: {})",
+            status.profile,
+            status.output_mode.as_str(),
+            status.profiles.join(", ")
+        );
+    }
+
+    if let Some(detected) = &status.detected_language {
+        println!(
+            "Detected Language: {} (probability {:.2})",
+            detected,
+            status.language_probability.unwrap_or(0.0)
+        );
+    }
+
+    Ok(())
+}
+
+fn run_devices() -> Result<()> {
+    let devices = audio::list_devices()?;
+    if devices.is_empty() {
+        println!("No input devices found");
+        return Ok(());
+    }
+
+    for device in devices {
+        println!(
+            "{}{}",
+            device.name,
+            if device.default { " (default)" } else { "" }
+        );
+        for config in &device.configs {
+            println!("    {}", config);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the configured vocabulary; a broken file is logged and ignored.
+fn load_vocabulary(config: &SttConfig) -> Vocabulary {
+    Vocabulary::load(&config.vocabulary_file).unwrap_or_else(|e| {
+        warn!("Ignoring vocabulary: {:#}", e);
+        Vocabulary::default()
+    })
+}
+
+fn run_transcribe_file(
+    config: &SttConfig,
+    file: &std::path::Path,
+    format: OutputFormat,
+    output: Option<&std::path::Path>,
+    hint: Option<String>,
+) -> Result<()> {
+    let audio = audio_file::load(file)?;
+    let mut transcriber = Transcriber::new(&config.engine, &config.model_path)
+        .context("Failed to load speech engine")?;
+
+    let started = Instant::now();
+    let options = DecodeOptions {
+        hint,
+        ..config.decode_options(&load_vocabulary(config))
+    };
+    let transcript = transcriber.transcribe(&audio, &options)?;
+    let result = TranscriptionResult::new(
+        transcript,
+        audio.len(),
+        started.elapsed().as_millis() as u64,
+    )
+    // Voice commands are for live typing; in a file they are just words
+    .postprocessed(&PostProcessor::new(&config.postprocess)?);
+
+    let options = &config.subtitles;
+    let rendered = match format {
+        OutputFormat::Text => format!("{}\n", result.text),
+        OutputFormat::Json => subtitles::render(&result, ExportFormat::Json, options)?,
+        OutputFormat::Srt => subtitles::render(&result, ExportFormat::Srt, options)?,
+        OutputFormat::Vtt => subtitles::render(&result, ExportFormat::Vtt, options)?,
+    };
+
+    match output {
+        Some(path) => std::fs::write(path, rendered)
+            .with_context(|| format!("Failed to write {}", path.display()))?,
+        None => print!("{}", rendered),
+    }
+
     Ok(())
 }
+
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
@@ -278,6 +392,10 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(Commands::Devices) = args.command {
+        return run_devices();
+    }
+
     if let Some(Commands::Refresh) = args.command {
         let stt_config = load_config(&args);
         if let Err(e) = run_refresh_client(stt_config).await {
@@ -286,187 +404,64 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
-    let mut stt_config = load_config(&args);
+    if let Some(Commands::Transcribe {
+        file,
+        format,
+        output,
+        hint,
+    }) = &args.command
+    {
+        let stt_config = load_config(&args);
+        stt_config.validate()?;
+        let stt_config = stt_config.with_profile(&stt_config.profile)?;
+        return run_transcribe_file(&stt_config, file, *format, output.as_deref(), hint.clone());
+    }
+
+    // Settings as loaded, and with the active profile applied
+    let base_config = load_config(&args);
+    base_config.validate()?;
+    let stt_config = base_config.with_profile(&base_config.profile)?;
 
     info!("Starting Telora Daemon...");
+    info!("Profile: {}", stt_config.profile);
+    info!("Using engine: {}", stt_config.engine);
     info!("Using model: {}", stt_config.model_path);
     info!("Language: {}", stt_config.language);
 
     // 1. Initialize Components
-    let mut transcriber =
-        Transcriber::new(&stt_config.model_path).context("Failed to load Whisper model")?;
-
-    // Audio Engine initialization
-    let rb = HeapRb::<f32>::new(16000 * 30); // 30 seconds buffer
-    let (producer, mut consumer) = rb.split();
-
-    let mut audio_engine = AudioEngine::new().context("Failed to init audio engine")?;
-    audio_engine
-        .start(producer)
-        .context("Failed to start audio engine")?;
+    let events = events::channel();
+    let mut pool = ModelPool::new(&stt_config.engine, &stt_config.model_pool);
+    pool.preload(&stt_config.model_pool.preload);
+    pool.get(&stt_config.model_path)
+        .context("Failed to load speech engine")?;
+    // Inference runs on its own thread from here on
+    let (worker, results_rx) =
+        Worker::spawn(pool, events.clone()).context("Failed to start transcription worker")?;
+
+    // Audio Engine initialization; the daemon opens and closes the stream
+    let audio_engine = AudioEngine::new().context("Failed to init audio engine")?;
 
     // Socket
-    let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
-    let socket_server = SocketServer::bind(SOCKET_PATH, cmd_tx).context("Failed to bind socket")?;
+    let (cmd_tx, cmd_rx) = mpsc::channel(32);
+    let socket_server =
+        SocketServer::bind(SOCKET_PATH, cmd_tx, events.clone()).context("Failed to bind socket")?;
 
     tokio::spawn(async move {
         socket_server.run().await;
     });
 
     // 2. Event Loop
-    let mut state = State::Idle;
-    let mut audio_buffer: Vec<f32> = Vec::with_capacity(16000 * 30); // Linear buffer for recording
-    let chunk_size = 512;
-    let mut chunk_buf: Vec<f32> = Vec::with_capacity(chunk_size);
-    let mut response_tx_opt: Option<oneshot::Sender<String>> = None;
-    let mut pending_result: Option<String> = None;
+    let daemon = Daemon::new(
+        base_config,
+        stt_config,
+        worker,
+        results_rx,
+        Box::new(audio_engine),
+        events,
+    )?;
 
     info!("System Ready. Waiting for commands on {}", SOCKET_PATH);
+    daemon.run(cmd_rx).await;
 
-    loop {
-        // Non-blocking check for commands
-        if let Ok(cmd) = cmd_rx.try_recv() {
-            match cmd {
-                Command::Start => {
-                    info!("Command: START");
-                    state = State::Recording;
-                    audio_buffer.clear();
-                    pending_result = None;
-                }
-                Command::Stop { response_tx } => {
-                    info!("Command: STOP");
-                    match state {
-                        State::Recording => {
-                            state = State::Processing;
-                            response_tx_opt = Some(response_tx);
-                        }
-                        State::Processing => {
-                            response_tx_opt = Some(response_tx);
-                        }
-                        State::Idle => {
-                            if let Some(res) = pending_result.take() {
-                                let _ = response_tx.send(res);
-                            } else {
-                                let _ = response_tx.send("".to_string());
-                            }
-                        }
-                    }
-                }
-                Command::Cancel => {
-                    info!("Command: CANCEL");
-                    state = State::Idle;
-                    audio_buffer.clear();
-                    response_tx_opt = None;
-                    pending_result = None;
-                }
-                Command::GetStatus { response_tx } => {
-                    let status_resp = StatusResponse {
-                        active: true,
-                        pid: std::process::id(),
-                        model_path: stt_config.model_path.clone(),
-                        language: stt_config.language.clone(),
-                        max_recording_seconds: stt_config.max_recording_seconds,
-                        state: match state {
-                            State::Idle => "Idle".to_string(),
-                            State::Recording => "Recording".to_string(),
-                            State::Processing => "Processing".to_string(),
-                        },
-                    };
-                    let _ = response_tx.send(status_resp);
-                }
-                Command::ReloadConfig {
-                    new_config,
-                    response_tx,
-                } => {
-                    info!("Command: REFRESH");
-                    let mut reload_transcriber = false;
-                    if new_config.model_path != stt_config.model_path {
-                        reload_transcriber = true;
-                    }
-
-                    stt_config = new_config;
-
-                    if reload_transcriber {
-                        info!("Model path changed, reloading transcriber...");
-                        match Transcriber::new(&stt_config.model_path) {
-                            Ok(new_transcriber) => {
-                                transcriber = new_transcriber;
-                                info!("Transcriber reloaded successfully.");
-                                let _ = response_tx.send(Ok(()));
-                            }
-                            Err(e) => {
-                                error!("Failed to reload transcriber: {}", e);
-                                let _ = response_tx
-                                    .send(Err(anyhow::anyhow!("Failed to load model: {}", e)));
-                            }
-                        }
-                    } else {
-                        info!("Configuration updated (no model change).");
-                        let _ = response_tx.send(Ok(()));
-                    }
-                }
-            }
-        }
-
-        // Process Audio from RingBuffer
-        let available = consumer.len();
-        if available >= chunk_size {
-            for _ in 0..chunk_size {
-                if let Some(sample) = consumer.pop() {
-                    chunk_buf.push(sample);
-                }
-            }
-
-            // If Recording, save to buffer
-            if state == State::Recording {
-                // Safety limit: User-defined or default maximum time
-                if audio_buffer.len() < 16000 * stt_config.max_recording_seconds as usize {
-                    audio_buffer.extend_from_slice(&chunk_buf);
-                } else {
-                    warn!(
-                        "Audio buffer limit reached ({}s). Stopping recording automatically.",
-                        stt_config.max_recording_seconds
-                    );
-                    state = State::Processing;
-                    // Notify client to stop UI and request result
-                    tokio::spawn(async move {
-                        notify_client_auto_stop().await;
-                    });
-                }
-            }
-
-            chunk_buf.clear();
-        } else {
-            // Sleep briefly to avoid busy loop
-            sleep(Duration::from_millis(5)).await;
-        }
-
-        // Processing State
-        if state == State::Processing {
-            info!("Processing {} samples...", audio_buffer.len());
-
-            let text = if audio_buffer.is_empty() {
-                warn!("Audio buffer empty, skipping transcription.");
-                "".to_string()
-            } else {
-                match transcriber.transcribe(&audio_buffer, Some(&stt_config.language)) {
-                    Ok(text) => text,
-                    Err(e) => {
-                        error!("Transcription failed: {}", e);
-                        format!("ERROR: {}", e)
-                    }
-                }
-            };
-
-            if let Some(tx) = response_tx_opt.take() {
-                let _ = tx.send(text);
-                pending_result = None;
-            } else {
-                pending_result = Some(text);
-            }
-
-            state = State::Idle;
-            audio_buffer.clear();
-        }
-    }
+    Ok(())
 }
@@ -0,0 +1,143 @@
+use anyhow::{Result, anyhow};
+
+use crate::transcriber::{Capabilities, DecodeOptions, Segment, SpeechEngine, Transcript};
+
+const SAMPLES_PER_MS: usize = 16;
+// Audio is scanned in blocks of this length
+const BLOCK_MS: usize = 100;
+// Blocks with an RMS level above this count as speech
+const SPEECH_RMS: f32 = 0.01;
+
+/// Deterministic engine for tests and for running the daemon without a model file.
+///
+/// Every run of non-silent audio becomes one segment whose text describes its length,
+/// e.g. " 1200 ms of speech". Models whose file name starts with "missing" fail to
+/// load, so tests can exercise load errors; any other path loads.
+#[derive(Default)]
+pub struct MockEngine;
+
+impl SpeechEngine for MockEngine {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn check(&self, model_path: &str) -> Result<()> {
+        let name = model_path.rsplit('/').next().unwrap_or(model_path);
+        if name.starts_with("missing") {
+            return Err(anyhow!("Model file not found: {}", model_path));
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, model_path: &str) -> Result<()> {
+        self.check(model_path)
+    }
+
+    fn transcribe(&mut self, audio: &[f32], options: &DecodeOptions) -> Result<Transcript> {
+        let block = BLOCK_MS * SAMPLES_PER_MS;
+        let mut segments = Vec::new();
+        let mut run_start: Option<usize> = None;
+
+        for (i, chunk) in audio.chunks(block).enumerate() {
+            if options.cancel.is_cancelled() {
+                return Err(anyhow!("Transcription cancelled"));
+            }
+            let rms = (chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32).sqrt();
+            match (rms > SPEECH_RMS, run_start) {
+                (true, None) => run_start = Some(i),
+                (false, Some(start)) => {
+                    segments.push(segment(start, i));
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(start) = run_start {
+            segments.push(segment(start, audio.len().div_ceil(block)));
+        }
+
+        Ok(Transcript {
+            language: options.language.clone(),
+            language_probability: None,
+            segments,
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            language_detection: false,
+            token_probabilities: false,
+        }
+    }
+
+    fn unload(&mut self) {}
+}
+
+fn segment(start_block: usize, end_block: usize) -> Segment {
+    let start_ms = (start_block * BLOCK_MS) as u64;
+    let end_ms = (end_block * BLOCK_MS) as u64;
+    Segment {
+        start_ms,
+        end_ms,
+        text: format!(" {} ms of speech", end_ms - start_ms),
+        confidence: 1.0,
+        tokens: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `speech` and `silence` alternate, in milliseconds.
+    fn audio(pattern: &[(bool, usize)]) -> Vec<f32> {
+        pattern
+            .iter()
+            .flat_map(|&(speech, ms)| {
+                let level = if speech { 0.3 } else { 0.0 };
+                (0..ms * SAMPLES_PER_MS).map(move |i| if i % 2 == 0 { level } else { -level })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_segment_per_speech_run() {
+        let audio = audio(&[(false, 500), (true, 1200), (false, 800), (true, 300)]);
+        let transcript = MockEngine
+            .transcribe(&audio, &DecodeOptions::for_test("es"))
+            .unwrap();
+
+        let spans: Vec<_> = transcript
+            .segments
+            .iter()
+            .map(|s| (s.start_ms, s.end_ms, s.text.as_str()))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (500, 1700, " 1200 ms of speech"),
+                (2500, 2800, " 300 ms of speech")
+            ]
+        );
+        assert_eq!(transcript.language, "es");
+    }
+
+    #[test]
+    fn silence_gives_no_segments() {
+        let transcript = MockEngine
+            .transcribe(&audio(&[(false, 2000)]), &DecodeOptions::for_test("es"))
+            .unwrap();
+        assert!(transcript.segments.is_empty());
+    }
+
+    #[test]
+    fn cancelled_decode_fails() {
+        let options = DecodeOptions::for_test("es");
+        options.cancel.cancel();
+        assert!(
+            MockEngine
+                .transcribe(&audio(&[(true, 500)]), &options)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::transcriber::{Transcriber, create_engine};
+
+/// Settings for the `[model_pool]` config table.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelPoolConfig {
+    /// Memory the resident models may use, in MB; 0 keeps a single model loaded
+    pub memory_mb: u64,
+    /// Models loaded at startup besides `model_path`, resolved the same way
+    pub preload: Vec<String>,
+}
+
+struct PooledModel {
+    model_path: String,
+    size: u64,
+    transcriber: Transcriber,
+}
+
+/// Loaded models, least recently used first.
+///
+/// A model's memory use is estimated from the size of its file, which for ggml
+/// models is close to what whisper allocates.
+pub struct ModelPool {
+    engine: String,
+    config: ModelPoolConfig,
+    budget: u64,
+    models: Vec<PooledModel>,
+}
+
+impl ModelPool {
+    pub fn new(engine: &str, config: &ModelPoolConfig) -> Self {
+        Self {
+            engine: engine.to_string(),
+            config: config.clone(),
+            budget: config.memory_mb * 1024 * 1024,
+            models: Vec::new(),
+        }
+    }
+
+    /// Applies new settings. Changing the engine unloads every model.
+    pub fn configure(&mut self, engine: &str, config: &ModelPoolConfig) {
+        if engine != self.engine {
+            info!("Engine changed to {}, unloading all models", engine);
+            self.models.clear();
+            self.engine = engine.to_string();
+        }
+        self.config = config.clone();
+        self.budget = config.memory_mb * 1024 * 1024;
+        // A smaller budget takes effect right away; the current model always stays
+        while self.models.len() > 1 && !self.fits(0) {
+            self.evict();
+        }
+    }
+
+    /// The engine and settings last applied.
+    pub fn settings(&self) -> (String, ModelPoolConfig) {
+        (self.engine.clone(), self.config.clone())
+    }
+
+    /// Loads models ahead of time; failures are logged and skipped.
+    pub fn preload(&mut self, model_paths: &[String]) {
+        for model_path in model_paths {
+            if let Err(e) = self.get(model_path) {
+                warn!("Failed to preload {}: {:#}", model_path, e);
+            }
+        }
+    }
+
+    /// The transcriber for `model_path`, loading it if needed.
+    pub fn get(&mut self, model_path: &str) -> Result<&mut Transcriber> {
+        if let Some(index) = self.models.iter().position(|m| m.model_path == model_path) {
+            let model = self.models.remove(index);
+            self.models.push(model);
+        } else {
+            let size = std::fs::metadata(model_path).map(|m| m.len()).unwrap_or(0);
+            let mut engine = create_engine(&self.engine)?;
+            // A model that is not there costs nothing; room is made only for one that is,
+            // and before it loads, so the budget holds throughout
+            engine
+                .check(model_path)
+                .with_context(|| format!("Failed to load {}", model_path))?;
+            while !self.models.is_empty() && !self.fits(size) {
+                self.evict();
+            }
+
+            info!("Loading model {} ({} MB)", model_path, size / (1024 * 1024));
+            engine
+                .load(model_path)
+                .with_context(|| format!("Failed to load {}", model_path))?;
+            let transcriber = Transcriber::from_engine(engine);
+            self.models.push(PooledModel {
+                model_path: model_path.to_string(),
+                size,
+                transcriber,
+            });
+        }
+
+        let model = self.models.last_mut().expect("model was just added");
+        Ok(&mut model.transcriber)
+    }
+
+    /// The most recently used transcriber.
+    pub fn current(&self) -> Option<&Transcriber> {
+        self.models.last().map(|m| &m.transcriber)
+    }
+
+    /// Paths of the resident models, least recently used first.
+    pub fn loaded(&self) -> Vec<String> {
+        self.models.iter().map(|m| m.model_path.clone()).collect()
+    }
+
+    fn fits(&self, extra: u64) -> bool {
+        if self.budget == 0 {
+            return self.models.is_empty();
+        }
+        let used: u64 = self.models.iter().map(|m| m.size).sum();
+        used + extra <= self.budget
+    }
+
+    fn evict(&mut self) {
+        let model = self.models.remove(0);
+        info!("Unloading model {}", model.model_path);
+    }
+}
+
+/// Finds a model given by file name in the usual model directories.
+pub fn resolve_model_path(model_path: &str, home: &str) -> String {
+    if std::path::Path::new(model_path).exists() {
+        return model_path.to_string();
+    }
+
+    let filename = if model_path.contains('/') {
+        // If it contains a slash but doesn't exist, we'll still try to see if it's just the end part
+        std::path::Path::new(model_path)
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or(model_path)
+    } else {
+        model_path
+    };
+
+    let candidates = vec![
+        format!("{}/.local/share/telora/models/{}", home, filename),
+        format!("/usr/share/telora/models/{}", filename),
+        format!("models/{}", filename),
+        filename.to_string(),
+    ];
+
+    candidates
+        .into_iter()
+        .find(|path| std::path::Path::new(path).exists())
+        .unwrap_or_else(|| model_path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    /// Sparse files stand in for models; the mock engine never reads them.
+    fn model_file(dir: &str, name: &str, size_mb: u64) -> String {
+        let dir = std::env::temp_dir().join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::File::create(&path)
+            .unwrap()
+            .set_len(size_mb * MB)
+            .unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn pool(memory_mb: u64) -> ModelPool {
+        ModelPool::new(
+            "mock",
+            &ModelPoolConfig {
+                memory_mb,
+                preload: Vec::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn evicts_least_recently_used_over_budget() {
+        let tiny = model_file("telora-pool-lru", "tiny.bin", 1);
+        let base = model_file("telora-pool-lru", "base.bin", 2);
+        let medium = model_file("telora-pool-lru", "medium.bin", 3);
+
+        let mut pool = pool(5);
+        pool.get(&tiny).unwrap();
+        pool.get(&base).unwrap();
+        pool.get(&tiny).unwrap();
+        assert_eq!(pool.loaded(), vec![base.clone(), tiny.clone()]);
+
+        // 1 + 2 + 3 MB does not fit; base was used least recently
+        pool.get(&medium).unwrap();
+        assert_eq!(pool.loaded(), vec![tiny.clone(), medium.clone()]);
+    }
+
+    #[test]
+    fn zero_budget_keeps_one_model() {
+        let tiny = model_file("telora-pool-single", "tiny.bin", 1);
+        let base = model_file("telora-pool-single", "base.bin", 1);
+
+        let mut pool = pool(0);
+        pool.preload(&[tiny.clone(), base.clone()]);
+        assert_eq!(pool.loaded(), vec![base]);
+    }
+
+    #[test]
+    fn changing_the_engine_unloads_everything() {
+        let tiny = model_file("telora-pool-engine", "tiny.bin", 1);
+
+        let mut pool = pool(10);
+        pool.get(&tiny).unwrap();
+        pool.configure("whisper", &ModelPoolConfig::default());
+        assert!(pool.loaded().is_empty());
+        assert!(pool.current().is_none());
+    }
+
+    #[test]
+    fn failed_load_keeps_the_resident_model() {
+        let tiny = model_file("telora-pool-failed", "tiny.bin", 1);
+        let missing = model_file("telora-pool-failed", "missing.bin", 1);
+
+        let mut pool = pool(0);
+        pool.get(&tiny).unwrap();
+        assert!(pool.get(&missing).is_err());
+        assert_eq!(pool.loaded(), vec![tiny]);
+        assert!(pool.current().is_some());
+    }
+}
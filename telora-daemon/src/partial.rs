@@ -0,0 +1,162 @@
+use anyhow::Result;
+
+use crate::transcriber::{DecodeOptions, Segment, Transcriber, Transcript, join_segments};
+
+const SAMPLES_PER_MS: usize = 16;
+
+/// Transcribes a growing recording in sliding windows.
+///
+/// Text from segments that whisper has closed is committed once the uncommitted
+/// window grows past `window_seconds`, so each update and the final pass only ever
+/// decode the tail of the recording.
+pub struct PartialTranscriber {
+    interval_samples: usize,
+    window_samples: usize,
+    committed: Vec<Segment>,
+    committed_samples: usize,
+    last_update: usize,
+}
+
+impl PartialTranscriber {
+    pub fn new(interval_ms: u32, window_seconds: u32) -> Self {
+        Self {
+            interval_samples: interval_ms as usize * SAMPLES_PER_MS,
+            window_samples: window_seconds as usize * 1000 * SAMPLES_PER_MS,
+            committed: Vec::new(),
+            committed_samples: 0,
+            last_update: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.committed.clear();
+        self.committed_samples = 0;
+        self.last_update = 0;
+    }
+
+    /// Whether enough new audio has arrived since the last update.
+    pub fn is_due(&self, audio_len: usize) -> bool {
+        audio_len >= self.last_update + self.interval_samples
+    }
+
+    /// Transcribes the uncommitted tail and returns the full running hypothesis.
+    pub fn update(
+        &mut self,
+        transcriber: &mut Transcriber,
+        audio: &[f32],
+        options: &DecodeOptions,
+    ) -> Result<String> {
+        self.last_update = audio.len();
+
+        let tail = &audio[self.committed_samples..];
+        let mut segments = self.decode(transcriber, tail, options)?.segments;
+
+        if tail.len() >= self.window_samples && segments.len() > 1 {
+            // Keep the last segment open: it may still be cut mid-word
+            let open = segments.split_off(segments.len() - 1);
+            self.committed.append(&mut segments);
+            self.committed_samples = (open[0].start_ms as usize * SAMPLES_PER_MS).min(audio.len());
+            segments = open;
+        }
+
+        let mut hypothesis = self.committed.clone();
+        hypothesis.extend(segments);
+        Ok(join_segments(&hypothesis))
+    }
+
+    /// Transcribes whatever has not been committed yet and returns the whole transcript.
+    pub fn finish(
+        &mut self,
+        transcriber: &mut Transcriber,
+        audio: &[f32],
+        options: &DecodeOptions,
+    ) -> Result<Transcript> {
+        let tail = &audio[self.committed_samples.min(audio.len())..];
+        let mut transcript = if tail.is_empty() {
+            Transcript {
+                language: options.language.clone(),
+                ..Transcript::default()
+            }
+        } else {
+            self.decode(transcriber, tail, options)?
+        };
+
+        let mut segments = std::mem::take(&mut self.committed);
+        segments.append(&mut transcript.segments);
+        transcript.segments = segments;
+        self.reset();
+        Ok(transcript)
+    }
+
+    /// Decodes part of the recording, with timestamps relative to its start.
+    fn decode(
+        &self,
+        transcriber: &mut Transcriber,
+        tail: &[f32],
+        options: &DecodeOptions,
+    ) -> Result<Transcript> {
+        let offset_ms = (self.committed_samples / SAMPLES_PER_MS) as u64;
+        let mut transcript = transcriber.transcribe(tail, options)?;
+        for segment in &mut transcript.segments {
+            segment.start_ms += offset_ms;
+            segment.end_ms += offset_ms;
+        }
+        Ok(transcript)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::MockEngine;
+
+    /// Five seconds, each with 600 ms of speech followed by 400 ms of silence.
+    fn audio() -> Vec<f32> {
+        (0..5 * 1000 * SAMPLES_PER_MS)
+            .map(|i| {
+                let speech = i % (1000 * SAMPLES_PER_MS) < 600 * SAMPLES_PER_MS;
+                match (speech, i % 2) {
+                    (true, 0) => 0.3,
+                    (true, _) => -0.3,
+                    _ => 0.0,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn committed_segments_keep_their_timestamps() {
+        let mut transcriber = Transcriber::from_engine(Box::new(MockEngine));
+        let mut partial = PartialTranscriber::new(1000, 2);
+        let audio = audio();
+
+        let mut hypothesis = String::new();
+        for seconds in 1..=5 {
+            let len = seconds * 1000 * SAMPLES_PER_MS;
+            assert!(partial.is_due(len));
+            hypothesis = partial
+                .update(
+                    &mut transcriber,
+                    &audio[..len],
+                    &DecodeOptions::for_test("es"),
+                )
+                .unwrap();
+            assert!(!partial.is_due(len));
+        }
+        assert_eq!(hypothesis.matches("600 ms of speech").count(), 5);
+        // Only the open window is decoded again
+        assert!(partial.committed_samples > 0);
+
+        let transcript = partial
+            .finish(&mut transcriber, &audio, &DecodeOptions::for_test("es"))
+            .unwrap();
+        let starts: Vec<u64> = transcript.segments.iter().map(|s| s.start_ms).collect();
+        assert_eq!(starts, vec![0, 1000, 2000, 3000, 4000]);
+        assert!(
+            transcript
+                .segments
+                .iter()
+                .all(|s| s.end_ms - s.start_ms == 600)
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use anyhow::{Context, Result};
+use regex::{NoExpand, Regex};
+use serde::{Deserialize, Serialize};
+
+/// Settings for the `[postprocess]` config table. Every stage is off by default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessConfig {
+    /// Apply the `replacements` list
+    pub replacements_enabled: bool,
+    pub replacements: Vec<Replacement>,
+    /// Drop hesitations such as "eh" or "um"
+    pub remove_fillers: bool,
+    /// Filler words to drop; empty uses the built-in list for the language
+    pub fillers: Vec<String>,
+    /// Turn spoken "coma", "punto", "new line"... into symbols
+    pub spoken_punctuation: bool,
+    /// Capitalize the first letter of every sentence
+    pub capitalize: bool,
+    /// End the text with a single space, so consecutive results do not run together
+    pub trailing_space: bool,
+}
+
+/// A replacement rule. `pattern` is matched literally unless `regex` is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replacement {
+    pub pattern: String,
+    pub replacement: String,
+    #[serde(default)]
+    pub regex: bool,
+}
+
+/// The compiled pipeline. Stages run in this order: fillers, spoken punctuation,
+/// replacements, capitalization, trailing space.
+pub struct PostProcessor {
+    config: PostProcessConfig,
+    replacements: Vec<(Regex, String)>,
+    spanish: LanguageRules,
+    other: LanguageRules,
+    tidy: Tidy,
+}
+
+impl PostProcessor {
+    pub fn new(config: &PostProcessConfig) -> Result<Self> {
+        let replacements = config
+            .replacements
+            .iter()
+            .map(|rule| {
+                let pattern = if rule.regex {
+                    rule.pattern.clone()
+                } else {
+                    regex::escape(&rule.pattern)
+                };
+                let re = Regex::new(&pattern)
+                    .with_context(|| format!("Invalid replacement pattern: {}", rule.pattern))?;
+                // Literal rules must not expand `$1` style references either
+                let replacement = if rule.regex {
+                    rule.replacement.clone()
+                } else {
+                    rule.replacement.replace('$', "$$")
+                };
+                Ok((re, replacement))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            config: config.clone(),
+            replacements,
+            spanish: LanguageRules::new("es", &config.fillers),
+            other: LanguageRules::new("", &config.fillers),
+            tidy: Tidy::new(),
+        })
+    }
+
+    pub fn apply(&self, text: &str, language: &str) -> String {
+        let mut text = text.to_string();
+        let rules = if language == "es" {
+            &self.spanish
+        } else {
+            &self.other
+        };
+
+        if self.config.remove_fillers
+            && let Some(fillers) = &rules.fillers
+        {
+            // Whisper usually sets fillers off with commas: "So, um, we..."
+            text = self.tidy.apply(&fillers.replace_all(&text, ""));
+        }
+        if self.config.spoken_punctuation {
+            for (re, symbol) in &rules.punctuation {
+                text = re.replace_all(&text, NoExpand(symbol)).into_owned();
+            }
+            text = self.tidy.apply(&text);
+        }
+        if self.config.replacements_enabled {
+            for (re, replacement) in &self.replacements {
+                text = re.replace_all(&text, replacement.as_str()).into_owned();
+            }
+        }
+        if self.config.capitalize {
+            text = capitalize(&text);
+        }
+
+        // Line breaks are kept: "new line" may be a whole dictated utterance
+        text = text.trim_matches([' ', '\t']).to_string();
+        if self.config.trailing_space && !text.is_empty() && !text.ends_with('\n') {
+            text.push(' ');
+        }
+        text
+    }
+}
+
+fn default_fillers(language: &str) -> &'static [&'static str] {
+    match language {
+        "es" => &["eh", "ehm", "em", "mmm", "hmm"],
+        _ => &["um", "uh", "uhm", "erm", "hmm", "mmm"],
+    }
+}
+
+/// Spoken forms and the symbol they become. Longer phrases come first so that
+/// "punto y coma" is not read as "punto" followed by "y coma".
+fn punctuation_table(language: &str) -> &'static [(&'static str, &'static str)] {
+    match language {
+        "es" => &[
+            ("punto y aparte", ".\n"),
+            ("punto y coma", ";"),
+            ("puntos suspensivos", "..."),
+            ("dos puntos", ":"),
+            ("signo de interrogación", "?"),
+            ("signo de exclamación", "!"),
+            ("nuevo párrafo", "\n\n"),
+            ("nueva línea", "\n"),
+            ("nueva linea", "\n"),
+            ("coma", ","),
+            ("punto", "."),
+        ],
+        _ => &[
+            ("question mark", "?"),
+            ("exclamation mark", "!"),
+            ("exclamation point", "!"),
+            ("new paragraph", "\n\n"),
+            ("new line", "\n"),
+            ("full stop", "."),
+            ("semicolon", ";"),
+            ("colon", ":"),
+            ("comma", ","),
+            ("period", "."),
+        ],
+    }
+}
+
+/// Filler and spoken punctuation patterns for one language, compiled once.
+struct LanguageRules {
+    /// `None` when there are no fillers to remove
+    fillers: Option<Regex>,
+    punctuation: Vec<(Regex, &'static str)>,
+}
+
+impl LanguageRules {
+    fn new(language: &str, custom_fillers: &[String]) -> Self {
+        let alternatives: Vec<String> = if custom_fillers.is_empty() {
+            default_fillers(language)
+                .iter()
+                .map(|f| regex::escape(f))
+                .collect()
+        } else {
+            custom_fillers.iter().map(|f| regex::escape(f)).collect()
+        };
+        let fillers = (!alternatives.is_empty()).then(|| {
+            Regex::new(&format!(r"(?i)\b(?:{})\b[,.]*", alternatives.join("|")))
+                .expect("filler pattern is escaped")
+        });
+
+        let punctuation = punctuation_table(language)
+            .iter()
+            .map(|(spoken, symbol)| {
+                let re = if symbol.contains('\n') {
+                    // Keep what precedes a line break, but not the space before it
+                    Regex::new(&format!(
+                        r"(?i)[ \t]*\b{}\b[,.]?[ \t]*",
+                        regex::escape(spoken)
+                    ))
+                } else {
+                    // Absorb the punctuation whisper adds around the spoken word: "Hola, coma, ..."
+                    Regex::new(&format!(
+                        r"(?i)[\s,.;:]*\b{}\b[,.;:]*",
+                        regex::escape(spoken)
+                    ))
+                }
+                .expect("punctuation pattern is escaped");
+                (re, *symbol)
+            })
+            .collect();
+
+        Self {
+            fillers,
+            punctuation,
+        }
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut sentence_start = true;
+    for c in text.chars() {
+        if sentence_start && c.is_alphabetic() {
+            out.extend(c.to_uppercase());
+            sentence_start = false;
+        } else {
+            out.push(c);
+            if matches!(c, '.' | '!' | '?' | '\n') {
+                sentence_start = true;
+            } else if !c.is_whitespace() && !matches!(c, '¿' | '¡' | '"' | '(') {
+                sentence_start = false;
+            }
+        }
+    }
+    out
+}
+
+/// Collapses repeated spaces and removes spaces before punctuation or around line breaks.
+struct Tidy {
+    spaces: Regex,
+    before_punct: Regex,
+    around_newline: Regex,
+}
+
+impl Tidy {
+    fn new() -> Self {
+        Self {
+            spaces: Regex::new(r"[ \t]{2,}").expect("valid pattern"),
+            before_punct: Regex::new(r"[ \t]+([,.;:!?])").expect("valid pattern"),
+            around_newline: Regex::new(r"[ \t]*\n[ \t]*").expect("valid pattern"),
+        }
+    }
+
+    fn apply(&self, text: &str) -> String {
+        let text = self.spaces.replace_all(text, " ");
+        let text = self.before_punct.replace_all(&text, "$1");
+        let text = self.around_newline.replace_all(&text, "\n");
+        text.trim_matches([' ', '\t']).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn processor(config: PostProcessConfig) -> PostProcessor {
+        PostProcessor::new(&config).unwrap()
+    }
+
+    #[test]
+    fn disabled_pipeline_only_trims() {
+        let p = processor(PostProcessConfig::default());
+        assert_eq!(p.apply("  hola eh coma  ", "es"), "hola eh coma");
+    }
+
+    #[test]
+    fn literal_and_regex_replacements() {
+        let p = processor(PostProcessConfig {
+            replacements_enabled: true,
+            replacements: vec![
+                Replacement {
+                    pattern: "tela ora".to_string(),
+                    replacement: "Telora ($)".to_string(),
+                    regex: false,
+                },
+                Replacement {
+                    pattern: r"(?i)\bpr (\d+)".to_string(),
+                    replacement: "PR #$1".to_string(),
+                    regex: true,
+                },
+            ],
+            ..Default::default()
+        });
+        assert_eq!(
+            p.apply("revisa tela ora en el pr 481", "es"),
+            "revisa Telora ($) en el PR #481"
+        );
+    }
+
+    #[test]
+    fn invalid_regex_is_rejected() {
+        let config = PostProcessConfig {
+            replacements: vec![Replacement {
+                pattern: "(".to_string(),
+                replacement: String::new(),
+                regex: true,
+            }],
+            ..Default::default()
+        };
+        assert!(PostProcessor::new(&config).is_err());
+    }
+
+    #[test]
+    fn removes_fillers() {
+        let p = processor(PostProcessConfig {
+            remove_fillers: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            p.apply("So, um, we should, uh, ship it.", "en"),
+            "So, we should, ship it."
+        );
+        assert_eq!(p.apply("Eh, vale, em, hecho.", "es"), "vale, hecho.");
+
+        let custom = processor(PostProcessConfig {
+            remove_fillers: true,
+            fillers: vec!["o sea".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(custom.apply("Es, o sea, fácil", "es"), "Es, fácil");
+    }
+
+    #[test]
+    fn converts_spoken_punctuation() {
+        let p = processor(PostProcessConfig {
+            spoken_punctuation: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            p.apply("Hola, coma, qué tal punto y coma bien punto", "es"),
+            "Hola, qué tal; bien."
+        );
+        assert_eq!(
+            p.apply("Primera línea. Nueva línea. Segunda", "es"),
+            "Primera línea.\nSegunda"
+        );
+        assert_eq!(
+            p.apply("Is it done question mark new paragraph yes", "en"),
+            "Is it done?\n\nyes"
+        );
+    }
+
+    #[test]
+    fn capitalizes_sentences() {
+        let p = processor(PostProcessConfig {
+            capitalize: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            p.apply("hola. ¿qué tal? bien\nvale", "es"),
+            "Hola. ¿Qué tal? Bien\nVale"
+        );
+    }
+
+    #[test]
+    fn controls_trailing_space() {
+        let on = processor(PostProcessConfig {
+            trailing_space: true,
+            ..Default::default()
+        });
+        assert_eq!(on.apply("hecho  ", "es"), "hecho ");
+        assert_eq!(on.apply("", "es"), "");
+
+        let punctuation = processor(PostProcessConfig {
+            spoken_punctuation: true,
+            trailing_space: true,
+            ..Default::default()
+        });
+        assert_eq!(punctuation.apply("Nueva línea.", "es"), "\n");
+    }
+
+    #[test]
+    fn stages_combine() {
+        let p = processor(PostProcessConfig {
+            remove_fillers: true,
+            spoken_punctuation: true,
+            capitalize: true,
+            trailing_space: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            p.apply("eh, hola coma esto es una prueba punto gracias", "es"),
+            "Hola, esto es una prueba. Gracias "
+        );
+    }
+}
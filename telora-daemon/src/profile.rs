@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Name that selects the top-level settings, with no profile applied.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// How the client delivers a result when the hotkey does not say.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    #[default]
+    Type,
+    Copy,
+}
+
+impl OutputMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OutputMode::Type => "type",
+            OutputMode::Copy => "copy",
+        }
+    }
+}
+
+/// A `[profiles.<name>]` table. Unset fields keep the top-level value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub model_path: Option<String>,
+    pub language: Option<String>,
+    pub max_recording_seconds: Option<u32>,
+    pub output_mode: Option<OutputMode>,
+}
+
+/// A profile name that is not in the config.
+#[derive(Debug)]
+pub struct UnknownProfile(pub String);
+
+impl fmt::Display for UnknownProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown profile: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownProfile {}
@@ -0,0 +1,190 @@
+use anyhow::{Context, Result};
+use rubato::{FftFixedIn, Resampler as _};
+
+/// Sample rate expected by Whisper.
+pub const TARGET_SAMPLE_RATE: u32 = 16000;
+
+/// Converts mono audio captured at the device sample rate to 16 kHz.
+///
+/// Input of any length is accepted; samples are buffered until a full chunk is
+/// available. When the device already runs at 16 kHz this is a pass-through.
+pub struct Resampler {
+    inner: Option<FftFixedIn<f32>>,
+    input_rate: u32,
+    chunk_size: usize,
+    pending: Vec<f32>,
+    skip: usize,
+    frames_in: usize,
+    frames_out: usize,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, chunk_size: usize) -> Result<Self> {
+        let inner = if input_rate == TARGET_SAMPLE_RATE {
+            None
+        } else {
+            Some(
+                FftFixedIn::<f32>::new(
+                    input_rate as usize,
+                    TARGET_SAMPLE_RATE as usize,
+                    chunk_size,
+                    1,
+                    1,
+                )
+                .context("Failed to create resampler")?,
+            )
+        };
+
+        let mut resampler = Self {
+            inner,
+            input_rate,
+            chunk_size,
+            pending: Vec::with_capacity(chunk_size * 2),
+            skip: 0,
+            frames_in: 0,
+            frames_out: 0,
+        };
+        resampler.reset();
+        Ok(resampler)
+    }
+
+    /// Clears buffered audio so the next recording starts from a clean state.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.frames_in = 0;
+        self.frames_out = 0;
+        if let Some(inner) = self.inner.as_mut() {
+            inner.reset();
+            // Drop the filter delay so the output lines up with the input
+            self.skip = inner.output_delay();
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Result<Vec<f32>> {
+        let Some(inner) = self.inner.as_mut() else {
+            return Ok(samples.to_vec());
+        };
+
+        self.pending.extend_from_slice(samples);
+        self.frames_in += samples.len();
+
+        let mut output = Vec::new();
+        let mut consumed = 0;
+        while self.pending.len() - consumed >= self.chunk_size {
+            let chunk = &self.pending[consumed..consumed + self.chunk_size];
+            let frames = inner.process(&[chunk], None).context("Resampling failed")?;
+            output.extend_from_slice(&frames[0]);
+            consumed += self.chunk_size;
+        }
+        self.pending.drain(..consumed);
+
+        Ok(self.trim_delay(output))
+    }
+
+    /// Resamples whatever is still buffered, including the filter tail, and resets.
+    pub fn flush(&mut self) -> Result<Vec<f32>> {
+        let Some(inner) = self.inner.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        // The tail is zero-padded; stop once the real input has been covered
+        let expected = self.frames_in * TARGET_SAMPLE_RATE as usize / self.input_rate as usize;
+
+        let mut output = Vec::new();
+        if !self.pending.is_empty() {
+            let frames = inner
+                .process_partial(Some(&[self.pending.as_slice()]), None)
+                .context("Resampling failed")?;
+            output.extend_from_slice(&frames[0]);
+        }
+        while self.frames_out + output.len() < expected + self.skip {
+            let frames = inner
+                .process_partial::<&[f32]>(None, None)
+                .context("Resampling failed")?;
+            if frames[0].is_empty() {
+                break;
+            }
+            output.extend_from_slice(&frames[0]);
+        }
+
+        let mut output = self.trim_delay(output);
+        let excess = self.frames_out.saturating_sub(expected);
+        output.truncate(output.len().saturating_sub(excess));
+
+        self.reset();
+        Ok(output)
+    }
+
+    fn trim_delay(&mut self, mut output: Vec<f32>) -> Vec<f32> {
+        let n = self.skip.min(output.len());
+        self.skip -= n;
+        output.drain(..n);
+        self.frames_out += output.len();
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn sine(rate: u32, freq: f32, seconds: f32) -> Vec<f32> {
+        let n = (rate as f32 * seconds) as usize;
+        (0..n)
+            .map(|i| (2.0 * PI * freq * i as f32 / rate as f32).sin() * 0.5)
+            .collect()
+    }
+
+    fn resample_all(rate: u32, input: &[f32]) -> Vec<f32> {
+        let mut resampler = Resampler::new(rate, 512).unwrap();
+        let mut output = Vec::new();
+        for chunk in input.chunks(512) {
+            output.extend(resampler.process(chunk).unwrap());
+        }
+        output.extend(resampler.flush().unwrap());
+        output
+    }
+
+    fn estimate_frequency(samples: &[f32], rate: u32) -> f32 {
+        // Skip the edges where the filter ramps up and down
+        let body = &samples[samples.len() / 10..samples.len() * 9 / 10];
+        let crossings = body
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        crossings as f32 * rate as f32 / body.len() as f32
+    }
+
+    fn check_rate(rate: u32) {
+        let input = sine(rate, 440.0, 2.0);
+        let output = resample_all(rate, &input);
+
+        let expected = 2 * TARGET_SAMPLE_RATE as usize;
+        assert!(
+            output.len().abs_diff(expected) <= 1,
+            "expected ~{} samples, got {}",
+            expected,
+            output.len()
+        );
+
+        let freq = estimate_frequency(&output, TARGET_SAMPLE_RATE);
+        assert!((freq - 440.0).abs() < 5.0, "expected ~440 Hz, got {}", freq);
+    }
+
+    #[test]
+    fn resamples_44100_to_16000() {
+        check_rate(44100);
+    }
+
+    #[test]
+    fn resamples_48000_to_16000() {
+        check_rate(48000);
+    }
+
+    #[test]
+    fn passes_through_16000() {
+        let input = sine(16000, 440.0, 0.5);
+        assert_eq!(resample_all(16000, &input), input);
+    }
+}
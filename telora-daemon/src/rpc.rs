@@ -0,0 +1,221 @@
+//! Newline-delimited JSON-RPC 2.0 framing shared by the daemon socket and its CLI clients.
+//!
+//! Every connection must start with a `hello` request carrying the client's
+//! `protocol_version`; other methods are rejected until the handshake succeeds.
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::fmt;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Standard JSON-RPC error codes
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const INTERNAL_ERROR: i32 = -32603;
+
+// Telora error codes
+pub const HANDSHAKE_REQUIRED: i32 = -32000;
+pub const UNSUPPORTED_VERSION: i32 = -32001;
+pub const MODEL_LOAD_FAILED: i32 = -32002;
+pub const TRANSCRIPTION_FAILED: i32 = -32003;
+pub const CANCELLED: i32 = -32004;
+pub const NO_TRANSCRIPT: i32 = -32005;
+pub const MICROPHONE_UNAVAILABLE: i32 = -32006;
+
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// Absent for notifications, which get no response
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn new(id: Value, outcome: Result<Value, RpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(value) => (Some(value), None),
+            Err(e) => (None, Some(e)),
+        };
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// A message without an id; used to push events to subscribers.
+#[derive(Debug, Serialize)]
+pub struct Notification<T: Serialize> {
+    pub jsonrpc: &'static str,
+    pub method: &'static str,
+    pub params: T,
+}
+
+impl<T: Serialize> Notification<T> {
+    pub fn new(method: &'static str, params: T) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            method,
+            params,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Checks the `hello` parameters sent by a client.
+pub fn check_hello(params: &Value) -> Result<Value, RpcError> {
+    let version = params
+        .get("protocol_version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing protocol_version"))?;
+
+    if version != u64::from(PROTOCOL_VERSION) {
+        return Err(RpcError::new(
+            UNSUPPORTED_VERSION,
+            format!(
+                "Unsupported protocol version {} (daemon speaks {})",
+                version, PROTOCOL_VERSION
+            ),
+        ));
+    }
+
+    Ok(json!({
+        "protocol_version": PROTOCOL_VERSION,
+        "daemon_version": env!("CARGO_PKG_VERSION"),
+    }))
+}
+
+/// A connection to the daemon socket that has completed the handshake.
+pub struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl Client {
+    pub async fn connect(path: &str) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .await
+            .context("Failed to connect to daemon")?;
+        let (reader, writer) = stream.into_split();
+        let mut client = Self {
+            reader: BufReader::new(reader),
+            writer,
+            next_id: 1,
+        };
+        client
+            .call("hello", json!({ "protocol_version": PROTOCOL_VERSION }))
+            .await?;
+        Ok(client)
+    }
+
+    /// Sends a request and waits for its result. Daemon errors are returned as `RpcError`.
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut line = serde_json::to_string(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }))?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .await
+            .context("Failed to send request")?;
+
+        let mut buf = String::new();
+        let n = self
+            .reader
+            .read_line(&mut buf)
+            .await
+            .context("Failed to read response from daemon")?;
+        if n == 0 {
+            return Err(anyhow!("Daemon closed the connection"));
+        }
+
+        let response: Response = serde_json::from_str(&buf).context("Failed to parse response")?;
+        if response.id != json!(id) {
+            return Err(anyhow!("Response id mismatch"));
+        }
+        match (response.result, response.error) {
+            (_, Some(e)) => Err(e.into()),
+            (Some(result), None) => Ok(result),
+            (None, None) => Ok(Value::Null),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_carries_either_result_or_error() {
+        let ok = serde_json::to_value(Response::new(json!(1), Ok(json!("done")))).unwrap();
+        assert_eq!(ok, json!({ "jsonrpc": "2.0", "id": 1, "result": "done" }));
+
+        let err = Response::new(json!(2), Err(RpcError::new(CANCELLED, "Cancelled")));
+        let err = serde_json::to_value(err).unwrap();
+        assert_eq!(err["error"]["code"], CANCELLED);
+        assert!(err.get("result").is_none());
+    }
+
+    #[test]
+    fn hello_needs_a_matching_version() {
+        assert!(check_hello(&json!({ "protocol_version": PROTOCOL_VERSION })).is_ok());
+        let missing = check_hello(&json!({})).unwrap_err();
+        assert_eq!(missing.code, INVALID_PARAMS);
+        let newer = check_hello(&json!({ "protocol_version": PROTOCOL_VERSION + 1 })).unwrap_err();
+        assert_eq!(newer.code, UNSUPPORTED_VERSION);
+    }
+}
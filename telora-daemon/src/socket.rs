@@ -1,10 +1,29 @@
-use anyhow::{Context, Result};
-use log::{error, info};
+use anyhow::{Context, Result, bail};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::os::unix::fs::PermissionsExt;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::UnixListener;
-use tokio::sync::{mpsc, oneshot};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::audio::{AudioConfig, DeviceInfo, NoSuchChannel, UnknownDevice};
+use crate::dsp::DspConfig;
+use crate::events::{Event, EventSender};
+use crate::model_pool::ModelPoolConfig;
+use crate::postprocess::{PostProcessConfig, PostProcessor};
+use crate::profile::{DEFAULT_PROFILE, OutputMode, Profile, UnknownProfile};
+use crate::rpc::{self, Notification, Request, Response, RpcError};
+use crate::subtitles::{ExportFormat, SubtitleOptions};
+use crate::transcriber::{
+    CancelToken, Capabilities, DecodeOptions, DecodingOverrides, DecodingParams, Transcript,
+};
+use crate::vad::{Vad, VadConfig};
+use crate::vocabulary::Vocabulary;
+use crate::voice_commands::{Action, CommandMatcher, VoiceCommandConfig};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StatusResponse {
@@ -14,38 +33,346 @@ pub struct StatusResponse {
     pub language: String,
     pub max_recording_seconds: u32,
     pub state: String,
+    /// Result of the last language detection, when `language` is "auto"
+    #[serde(default)]
+    pub detected_language: Option<String>,
+    #[serde(default)]
+    pub language_probability: Option<f32>,
+    #[serde(default)]
+    pub engine: String,
+    /// Where inference runs: "cpu", or "cuda" when the GPU initialized
+    #[serde(default)]
+    pub backend: String,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    /// Vocabulary file in use and how many terms it holds
+    #[serde(default)]
+    pub vocabulary_path: String,
+    #[serde(default)]
+    pub vocabulary_terms: usize,
+    /// Active profile and the output mode it asks the client to use
+    #[serde(default)]
+    pub profile: String,
+    #[serde(default)]
+    pub output_mode: OutputMode,
+    #[serde(default)]
+    pub profiles: Vec<String>,
+    /// Models held in memory, least recently used first
+    #[serde(default)]
+    pub loaded_models: Vec<String>,
+    /// "open" while the microphone is in use, "closed" once it has been released
+    #[serde(default)]
+    pub audio_stream: String,
+    /// Input device the open stream captures from
+    #[serde(default)]
+    pub audio_device: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct SttConfig {
+    /// Speech engine: "whisper", or "mock" to run without a model file
+    pub engine: String,
     pub model_path: String,
+    /// Language code, or "auto" to let whisper detect it
     pub language: String,
+    /// Languages considered by "auto" detection; empty means all
+    pub language_candidates: Vec<String>,
     pub max_recording_seconds: u32,
+    /// Stop a recording once this much silence follows speech, in milliseconds; 0 disables it
+    pub auto_stop_silence_ms: u32,
+    /// Speech needed before silence can stop a recording, so a cough does not
+    pub auto_stop_min_speech_ms: u32,
+    /// File with one term per line, passed to whisper as part of the initial prompt
+    pub vocabulary_file: String,
+    /// Transcribe while recording and push partial results to the client
+    pub streaming: bool,
+    pub partial_interval_ms: u32,
+    pub partial_window_seconds: u32,
+    /// Deprecated spelling of `[vad] min_level`; moved there by `migrate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vad_threshold: Option<f32>,
+    /// Deprecated spelling of `[vad] hangover_ms`; moved there by `migrate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vad_hangover_ms: Option<u32>,
+    /// Voice activity detection: the detector, its sensitivity and silence trimming
+    pub vad: VadConfig,
+    /// Cue layout used by `export`
+    pub subtitles: SubtitleOptions,
+    pub decoding: DecodingParams,
+    /// Text clean-up applied to every result before it is returned
+    pub postprocess: PostProcessConfig,
+    pub voice_commands: VoiceCommandConfig,
+    /// Models kept loaded so requests can switch between them without reloading
+    pub model_pool: ModelPoolConfig,
+    /// Microphone capture: when to release the device and how much audio to keep
+    pub audio: AudioConfig,
+    /// Filters applied to the audio before it is buffered
+    pub dsp: DspConfig,
+    /// What the client does with a result when the hotkey does not say: type or copy
+    pub output_mode: OutputMode,
+    /// Profile applied at startup; "default" uses the top-level settings
+    pub profile: String,
+    /// Named overrides selected with `telora profile <name>`
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Default for SttConfig {
+    fn default() -> Self {
+        Self {
+            engine: "whisper".to_string(),
+            model_path: "ggml-base.bin".to_string(),
+            language: "es".to_string(),
+            language_candidates: Vec::new(),
+            max_recording_seconds: 600,
+            auto_stop_silence_ms: 0,
+            auto_stop_min_speech_ms: 500,
+            vocabulary_file: String::new(),
+            streaming: false,
+            partial_interval_ms: 1000,
+            partial_window_seconds: 20,
+            vad_threshold: None,
+            vad_hangover_ms: None,
+            vad: VadConfig::default(),
+            subtitles: SubtitleOptions::default(),
+            decoding: DecodingParams::default(),
+            postprocess: PostProcessConfig::default(),
+            voice_commands: VoiceCommandConfig::default(),
+            model_pool: ModelPoolConfig::default(),
+            audio: AudioConfig::default(),
+            dsp: DspConfig::default(),
+            output_mode: OutputMode::default(),
+            profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl SttConfig {
+    /// Moves settings from keys that have been renamed to where they live now. The old
+    /// keys win, since a config that still has them was written for them.
+    pub fn migrate(&mut self) {
+        if let Some(level) = self.vad_threshold.take() {
+            warn!("vad_threshold is deprecated; use min_level in [vad]");
+            self.vad.min_level = level;
+        }
+        if let Some(ms) = self.vad_hangover_ms.take() {
+            warn!("vad_hangover_ms is deprecated; use hangover_ms in [vad]");
+            self.vad.hangover_ms = ms;
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.decoding
+            .validate()
+            .context("Invalid [decoding] config")?;
+        PostProcessor::new(&self.postprocess).context("Invalid [postprocess] config")?;
+        self.dsp.validate().context("Invalid [dsp] config")?;
+        self.vad.validate().context("Invalid [vad] config")?;
+        if self.profiles.contains_key(DEFAULT_PROFILE) {
+            bail!(
+                "\"{}\" is reserved and cannot name a profile",
+                DEFAULT_PROFILE
+            );
+        }
+        self.with_profile(&self.profile)?;
+        Ok(())
+    }
+
+    /// The settings with the named profile applied on top.
+    pub fn with_profile(&self, name: &str) -> Result<SttConfig> {
+        let mut config = self.clone();
+        if name.is_empty() || name == DEFAULT_PROFILE {
+            config.profile = DEFAULT_PROFILE.to_string();
+            return Ok(config);
+        }
+
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| UnknownProfile(name.to_string()))?;
+        if let Some(model_path) = &profile.model_path {
+            config.model_path = model_path.clone();
+        }
+        if let Some(language) = &profile.language {
+            config.language = language.clone();
+        }
+        if let Some(seconds) = profile.max_recording_seconds {
+            config.max_recording_seconds = seconds;
+        }
+        if let Some(mode) = profile.output_mode {
+            config.output_mode = mode;
+        }
+        config.profile = name.to_string();
+        Ok(config)
+    }
+
+    pub fn vad(&self) -> Result<Vad> {
+        Vad::new(&self.vad).context("Failed to set up voice activity detection")
+    }
+
+    pub fn decode_options(&self, vocabulary: &Vocabulary) -> DecodeOptions {
+        DecodeOptions {
+            language: self.language.clone(),
+            language_candidates: self.language_candidates.clone(),
+            decoding: self.decoding.clone(),
+            vocabulary: vocabulary.terms.clone(),
+            hint: None,
+            cancel: CancelToken::default(),
+        }
+    }
+
+    /// Decode options for one recording, with the request's overrides and hint applied.
+    pub fn decode_options_with(
+        &self,
+        vocabulary: &Vocabulary,
+        options: &StartOptions,
+    ) -> Result<DecodeOptions> {
+        Ok(DecodeOptions {
+            decoding: options.decoding.apply(&self.decoding)?,
+            hint: options.hint.clone(),
+            ..self.decode_options(vocabulary)
+        })
+    }
+}
+
+/// Per-recording parameters accepted by `start` and `dictate_start`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StartOptions {
+    /// Model for this recording, by file name or path; the configured one if unset
+    pub model: Option<String>,
+    pub decoding: DecodingOverrides,
+    /// Extra prompt text for this recording, such as names that are about to come up
+    pub hint: Option<String>,
+}
+
+/// Result of a recording, returned by `stop`.
+///
+/// `text` is the plain transcript; the flattened `language` and `segments` carry
+/// timestamps and confidences for tools that need them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TranscriptionResult {
+    pub text: String,
+    /// Length of the transcribed audio
+    pub duration_ms: u64,
+    /// Time spent decoding after the recording was stopped
+    pub latency_ms: u64,
+    /// What the client should do: type `text`, or run a recognised voice command
+    #[serde(default)]
+    pub action: Action,
+    #[serde(flatten)]
+    pub transcript: Transcript,
+}
+
+impl TranscriptionResult {
+    pub fn new(transcript: Transcript, samples: usize, latency_ms: u64) -> Self {
+        Self {
+            text: transcript.text(),
+            duration_ms: (samples / 16) as u64,
+            latency_ms,
+            action: Action::default(),
+            transcript,
+        }
+    }
+
+    /// Resolves the client action: a voice command if the whole utterance is one,
+    /// otherwise the post-processed text. Segments keep the raw text.
+    pub fn finish(mut self, commands: &CommandMatcher, processor: &PostProcessor) -> Self {
+        let language = &self.transcript.language;
+        match commands.find(&self.text, language) {
+            Some(action) => {
+                info!("Voice command: {:?}", action);
+                self.text.clear();
+                self.action = action;
+            }
+            None => return self.postprocessed(processor),
+        }
+        self
+    }
+
+    /// Applies the post-processing alone, for transcripts that are not typed live,
+    /// where a voice command would only remove content.
+    pub fn postprocessed(mut self, processor: &PostProcessor) -> Self {
+        self.text = processor.apply(&self.text, &self.transcript.language);
+        self.action = Action::Text {
+            text: self.text.clone(),
+        };
+        self
+    }
+}
+
+/// Why a `start` or `dictate_start` failed, so the client gets a matching error code.
+#[derive(Debug)]
+pub enum StartError {
+    /// Decoding overrides that do not validate
+    InvalidOptions(String),
+    Microphone(String),
+    ModelLoad(String),
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartError::InvalidOptions(message)
+            | StartError::Microphone(message)
+            | StartError::ModelLoad(message) => f.write_str(message),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Command {
-    Start,
+    Start {
+        options: StartOptions,
+        response_tx: oneshot::Sender<Result<(), StartError>>,
+    },
     Stop {
-        response_tx: oneshot::Sender<String>,
+        /// Decode with this model instead of the one chosen at `start`
+        model: Option<String>,
+        response_tx: oneshot::Sender<Result<TranscriptionResult, String>>,
     },
     Cancel,
+    DictateStart {
+        options: StartOptions,
+        response_tx: oneshot::Sender<Result<(), StartError>>,
+    },
+    DictateStop,
+    /// Render the most recent recording in the given format
+    Export {
+        format: ExportFormat,
+        response_tx: oneshot::Sender<Result<String, String>>,
+    },
     GetStatus {
         response_tx: oneshot::Sender<StatusResponse>,
     },
+    /// Switch to a named profile, reloading the model if it differs
+    SetProfile {
+        name: String,
+        response_tx: oneshot::Sender<Result<()>>,
+    },
     ReloadConfig {
-        new_config: SttConfig,
+        new_config: Box<SttConfig>,
         response_tx: oneshot::Sender<Result<()>>,
     },
+    ListDevices {
+        response_tx: oneshot::Sender<Result<Vec<DeviceInfo>>>,
+    },
+    /// Capture from another input device; replies with the name of the device chosen
+    SetDevice {
+        name: String,
+        response_tx: oneshot::Sender<Result<String>>,
+    },
 }
 
 pub struct SocketServer {
     listener: UnixListener,
     cmd_tx: mpsc::Sender<Command>,
+    events: EventSender,
 }
 
 impl SocketServer {
-    pub fn bind(path: &str, cmd_tx: mpsc::Sender<Command>) -> Result<Self> {
+    pub fn bind(path: &str, cmd_tx: mpsc::Sender<Command>, events: EventSender) -> Result<Self> {
         if std::fs::metadata(path).is_ok() {
             info!("Removing existing socket file: {}", path);
             std::fs::remove_file(path).context("Failed to remove existing socket")?;
@@ -60,151 +387,22 @@ impl SocketServer {
 
         info!("Listening on unix socket: {} (restricted to 0600)", path);
 
-        Ok(Self { listener, cmd_tx })
+        Ok(Self {
+            listener,
+            cmd_tx,
+            events,
+        })
     }
 
     pub async fn run(&self) {
         loop {
             match self.listener.accept().await {
-                Ok((mut stream, _addr)) => {
+                Ok((stream, _addr)) => {
                     let cmd_tx = self.cmd_tx.clone();
+                    let events = self.events.clone();
                     tokio::spawn(async move {
-                        let mut buf = [0; 2048]; // Increased buffer size for config JSON
-                        match stream.read(&mut buf).await {
-                            Ok(n) if n > 0 => {
-                                let command_str =
-                                    String::from_utf8_lossy(&buf[..n]).trim().to_string();
-                                info!("Received command: {}", command_str);
-
-                                if command_str.starts_with("REFRESH") {
-                                    let json_part =
-                                        command_str.strip_prefix("REFRESH").unwrap_or("").trim();
-                                    match serde_json::from_str::<SttConfig>(json_part) {
-                                        Ok(new_config) => {
-                                            let (tx, rx) = oneshot::channel();
-                                            if let Err(e) = cmd_tx
-                                                .send(Command::ReloadConfig {
-                                                    new_config,
-                                                    response_tx: tx,
-                                                })
-                                                .await
-                                            {
-                                                error!("Failed to send reload command: {}", e);
-                                                let _ = stream
-                                                    .write_all(b"ERROR: Internal channel error")
-                                                    .await;
-                                            } else {
-                                                match rx.await {
-                                                    Ok(Ok(())) => {
-                                                        let _ = stream
-                                                            .write_all(b"OK: Config reloaded")
-                                                            .await;
-                                                    }
-                                                    Ok(Err(e)) => {
-                                                        let _ = stream
-                                                            .write_all(
-                                                                format!("ERROR: {}", e).as_bytes(),
-                                                            )
-                                                            .await;
-                                                    }
-                                                    Err(_) => {
-                                                        let _ = stream.write_all(b"ERROR: Reload cancelled or failed").await;
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        Err(e) => {
-                                            error!("Failed to parse config JSON: {}", e);
-                                            let _ = stream
-                                                .write_all(
-                                                    format!("ERROR: Invalid config JSON: {}", e)
-                                                        .as_bytes(),
-                                                )
-                                                .await;
-                                        }
-                                    }
-                                    return;
-                                }
-
-                                match command_str.as_str() {
-                                    "START" => {
-                                        if let Err(e) = cmd_tx.send(Command::Start).await {
-                                            error!("Failed to send start command: {}", e);
-                                            let _ = stream
-                                                .write_all(b"ERROR: Internal channel error")
-                                                .await;
-                                        } else {
-                                            let _ = stream.write_all(b"STATUS: RECORDING").await;
-                                        }
-                                    }
-                                    "STOP" => {
-                                        let (tx, rx) = oneshot::channel();
-                                        if let Err(e) =
-                                            cmd_tx.send(Command::Stop { response_tx: tx }).await
-                                        {
-                                            error!("Failed to send stop command: {}", e);
-                                            let _ = stream
-                                                .write_all(b"ERROR: Internal channel error")
-                                                .await;
-                                        } else {
-                                            // Wait for the transcription result from the main loop
-                                            match rx.await {
-                                                Ok(text) => {
-                                                    let _ = stream.write_all(text.as_bytes()).await;
-                                                }
-                                                Err(_) => {
-                                                    let _ = stream.write_all(b"ERROR: Transcription cancelled or failed").await;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    "CANCEL" => {
-                                        let _ = cmd_tx.send(Command::Cancel).await;
-                                        let _ = stream.write_all(b"STATUS: CANCELLED").await;
-                                    }
-                                    "STATUS" => {
-                                        let (tx, rx) = oneshot::channel();
-                                        if let Err(e) = cmd_tx
-                                            .send(Command::GetStatus { response_tx: tx })
-                                            .await
-                                        {
-                                            error!("Failed to send status command: {}", e);
-                                            let _ = stream
-                                                .write_all(b"ERROR: Internal channel error")
-                                                .await;
-                                        } else {
-                                            match rx.await {
-                                                Ok(status) => {
-                                                    let json = serde_json::to_string(&status)
-                                                        .unwrap_or_else(|_| "{}".to_string());
-                                                    let _ = stream.write_all(json.as_bytes()).await;
-                                                }
-                                                Err(_) => {
-                                                    let _ = stream
-                                                        .write_all(b"ERROR: Failed to get status")
-                                                        .await;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    _ => {
-                                        let _ = stream.write_all(b"ERROR: Unknown command").await;
-                                    }
-                                };
-
-                                // TODO: Implementing full bidirectional wait for transcription is tricky here without a shared state or response channel.
-                                // Quick fix: The main loop will handle the logic, but how does it send back to THIS stream?
-                                // Architecture choice:
-                                // 1. Client connects, sends STOP, waits.
-                                // 2. Socket task sends StopRecording to Main.
-                                // 3. Socket task waits for Result from Main (via oneshot channel?).
-                                // 4. Socket task writes Result to Stream.
-                                //
-                                // Let's implement that pattern in the next step (Main).
-                                // For now, this is a good skeleton.
-                            }
-                            Ok(_) => {} // EOF
-                            Err(e) => error!("Failed to read from socket: {}", e),
+                        if let Err(e) = handle_connection(stream, cmd_tx, events).await {
+                            error!("Connection error: {}", e);
                         }
                     });
                 }
@@ -213,3 +411,573 @@ impl SocketServer {
         }
     }
 }
+
+/// Serves newline-delimited JSON-RPC requests until the client disconnects.
+///
+/// After `subscribe`, daemon events are interleaved with responses as `event` notifications.
+async fn handle_connection(
+    stream: UnixStream,
+    cmd_tx: mpsc::Sender<Command>,
+    events: EventSender,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut handshake_done = false;
+    let mut subscription: Option<broadcast::Receiver<Event>> = None;
+
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => match line? {
+                Some(line) => line,
+                None => break,
+            },
+            event = next_event(&mut subscription) => {
+                match event {
+                    Ok(event) => {
+                        let mut line = serde_json::to_string(&Notification::new("event", event))?;
+                        line.push('\n');
+                        writer.write_all(line.as_bytes()).await?;
+                    }
+                    Err(RecvError::Lagged(n)) => warn!("Subscriber lagging, dropped {} events", n),
+                    Err(RecvError::Closed) => subscription = None,
+                }
+                continue;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let value: Value = match serde_json::from_str(&line) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Unparseable request: {}", e);
+                let error = RpcError::new(rpc::PARSE_ERROR, format!("Parse error: {}", e));
+                write_response(&mut writer, Response::new(Value::Null, Err(error))).await?;
+                continue;
+            }
+        };
+        // Well-formed JSON, but not a request
+        let id = value.get("id").cloned().unwrap_or(Value::Null);
+        let request: Request = match serde_json::from_value(value) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Invalid request: {}", e);
+                let error = RpcError::new(rpc::INVALID_REQUEST, format!("Invalid request: {}", e));
+                write_response(&mut writer, Response::new(id, Err(error))).await?;
+                continue;
+            }
+        };
+
+        info!("Received request: {}", request.method);
+
+        let outcome = if request.jsonrpc != "2.0" {
+            Err(RpcError::new(rpc::INVALID_REQUEST, "Expected jsonrpc 2.0"))
+        } else if request.method == "hello" {
+            let outcome = rpc::check_hello(&request.params);
+            handshake_done = outcome.is_ok();
+            outcome
+        } else if !handshake_done {
+            Err(RpcError::new(
+                rpc::HANDSHAKE_REQUIRED,
+                "Send hello before any other request",
+            ))
+        } else if request.method == "subscribe" {
+            subscription = Some(events.subscribe());
+            Ok(json!({ "subscribed": true }))
+        } else if request.method == "unsubscribe" {
+            subscription = None;
+            Ok(json!({ "subscribed": false }))
+        } else {
+            dispatch(&cmd_tx, &request.method, request.params).await
+        };
+
+        // Notifications carry no id and get no response
+        if let Some(id) = request.id {
+            write_response(&mut writer, Response::new(id, outcome)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn next_event(
+    subscription: &mut Option<broadcast::Receiver<Event>>,
+) -> Result<Event, RecvError> {
+    match subscription {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn write_response(
+    writer: &mut (impl AsyncWriteExt + Unpin),
+    response: Response,
+) -> Result<()> {
+    let mut line = serde_json::to_string(&response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+async fn send(cmd_tx: &mpsc::Sender<Command>, command: Command) -> Result<(), RpcError> {
+    cmd_tx.send(command).await.map_err(|e| {
+        error!("Failed to send command: {}", e);
+        RpcError::new(rpc::INTERNAL_ERROR, "Internal channel error")
+    })
+}
+
+async fn dispatch(
+    cmd_tx: &mpsc::Sender<Command>,
+    method: &str,
+    params: Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "start" => {
+            let (tx, rx) = oneshot::channel();
+            let options = start_options(params)?;
+            send(
+                cmd_tx,
+                Command::Start {
+                    options,
+                    response_tx: tx,
+                },
+            )
+            .await?;
+            started(rx, "Recording").await
+        }
+        "stop" => {
+            let (tx, rx) = oneshot::channel();
+            let model = params
+                .get("model")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            send(
+                cmd_tx,
+                Command::Stop {
+                    model,
+                    response_tx: tx,
+                },
+            )
+            .await?;
+            // Wait for the transcription result from the main loop
+            match rx.await {
+                Ok(Ok(result)) => Ok(json!(result)),
+                Ok(Err(e)) => Err(RpcError::new(rpc::TRANSCRIPTION_FAILED, e)),
+                Err(_) => Err(RpcError::new(
+                    rpc::CANCELLED,
+                    "Transcription cancelled or failed",
+                )),
+            }
+        }
+        "cancel" => {
+            send(cmd_tx, Command::Cancel).await?;
+            Ok(json!({ "state": "Idle" }))
+        }
+        "dictate_start" => {
+            let (tx, rx) = oneshot::channel();
+            let options = start_options(params)?;
+            send(
+                cmd_tx,
+                Command::DictateStart {
+                    options,
+                    response_tx: tx,
+                },
+            )
+            .await?;
+            started(rx, "Dictating").await
+        }
+        "dictate_stop" => {
+            send(cmd_tx, Command::DictateStop).await?;
+            Ok(json!({ "state": "Idle" }))
+        }
+        "export" => {
+            let format = params
+                .get("format")
+                .and_then(Value::as_str)
+                .unwrap_or("srt")
+                .parse::<ExportFormat>()
+                .map_err(|e| RpcError::new(rpc::INVALID_PARAMS, e.to_string()))?;
+            let (tx, rx) = oneshot::channel();
+            send(
+                cmd_tx,
+                Command::Export {
+                    format,
+                    response_tx: tx,
+                },
+            )
+            .await?;
+            match rx.await {
+                Ok(Ok(content)) => Ok(json!({ "content": content })),
+                Ok(Err(e)) => Err(RpcError::new(rpc::NO_TRANSCRIPT, e)),
+                Err(_) => Err(RpcError::new(rpc::INTERNAL_ERROR, "Failed to export")),
+            }
+        }
+        "status" => {
+            let (tx, rx) = oneshot::channel();
+            send(cmd_tx, Command::GetStatus { response_tx: tx }).await?;
+            let status = rx
+                .await
+                .map_err(|_| RpcError::new(rpc::INTERNAL_ERROR, "Failed to get status"))?;
+            Ok(json!(status))
+        }
+        "profile" => {
+            let name = params
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| RpcError::new(rpc::INVALID_PARAMS, "Missing profile name"))?
+                .to_string();
+            let (tx, rx) = oneshot::channel();
+            send(
+                cmd_tx,
+                Command::SetProfile {
+                    name: name.clone(),
+                    response_tx: tx,
+                },
+            )
+            .await?;
+            match rx.await {
+                Ok(Ok(())) => Ok(json!({ "profile": name })),
+                Ok(Err(e)) if e.is::<UnknownProfile>() || e.is::<NoSuchChannel>() => {
+                    Err(RpcError::new(rpc::INVALID_PARAMS, e.to_string()))
+                }
+                Ok(Err(e)) => Err(RpcError::new(rpc::MODEL_LOAD_FAILED, e.to_string())),
+                Err(_) => Err(RpcError::new(rpc::CANCELLED, "Profile switch failed")),
+            }
+        }
+        "refresh" => {
+            let new_config: SttConfig = serde_json::from_value(params).map_err(|e| {
+                error!("Failed to parse config JSON: {}", e);
+                RpcError::new(rpc::INVALID_PARAMS, format!("Invalid config: {}", e))
+            })?;
+            new_config
+                .validate()
+                .map_err(|e| RpcError::new(rpc::INVALID_PARAMS, format!("{:#}", e)))?;
+            let (tx, rx) = oneshot::channel();
+            send(
+                cmd_tx,
+                Command::ReloadConfig {
+                    new_config: Box::new(new_config),
+                    response_tx: tx,
+                },
+            )
+            .await?;
+            match rx.await {
+                Ok(Ok(())) => Ok(Value::Null),
+                Ok(Err(e)) if e.is::<NoSuchChannel>() => {
+                    Err(RpcError::new(rpc::INVALID_PARAMS, format!("{:#}", e)))
+                }
+                Ok(Err(e)) => Err(RpcError::new(rpc::MODEL_LOAD_FAILED, e.to_string())),
+                Err(_) => Err(RpcError::new(rpc::CANCELLED, "Reload cancelled or failed")),
+            }
+        }
+        "list_devices" => {
+            let (tx, rx) = oneshot::channel();
+            send(cmd_tx, Command::ListDevices { response_tx: tx }).await?;
+            match rx.await {
+                Ok(Ok(devices)) => Ok(json!({ "devices": devices })),
+                Ok(Err(e)) => Err(RpcError::new(rpc::INTERNAL_ERROR, format!("{:#}", e))),
+                Err(_) => Err(RpcError::new(rpc::INTERNAL_ERROR, "Failed to list devices")),
+            }
+        }
+        "set_device" => {
+            // An empty name goes back to the system default
+            let name = params
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| RpcError::new(rpc::INVALID_PARAMS, "Missing device name"))?
+                .to_string();
+            let (tx, rx) = oneshot::channel();
+            send(
+                cmd_tx,
+                Command::SetDevice {
+                    name,
+                    response_tx: tx,
+                },
+            )
+            .await?;
+            match rx.await {
+                Ok(Ok(device)) => Ok(json!({ "device": device })),
+                Ok(Err(e)) if e.is::<UnknownDevice>() || e.is::<NoSuchChannel>() => {
+                    Err(RpcError::new(rpc::INVALID_PARAMS, e.to_string()))
+                }
+                Ok(Err(e)) => Err(RpcError::new(rpc::INTERNAL_ERROR, format!("{:#}", e))),
+                Err(_) => Err(RpcError::new(
+                    rpc::INTERNAL_ERROR,
+                    "Failed to switch devices",
+                )),
+            }
+        }
+        _ => Err(RpcError::new(
+            rpc::METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    }
+}
+
+/// Reads the optional parameters of `start` and `dictate_start`.
+fn start_options(params: Value) -> Result<StartOptions, RpcError> {
+    if params.is_null() {
+        return Ok(StartOptions::default());
+    }
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::new(rpc::INVALID_PARAMS, format!("Invalid options: {}", e)))
+}
+
+async fn started(
+    rx: oneshot::Receiver<Result<(), StartError>>,
+    state: &str,
+) -> Result<Value, RpcError> {
+    match rx.await {
+        Ok(Ok(())) => Ok(json!({ "state": state })),
+        Ok(Err(e)) => {
+            let code = match e {
+                StartError::InvalidOptions(_) => rpc::INVALID_PARAMS,
+                StartError::Microphone(_) => rpc::MICROPHONE_UNAVAILABLE,
+                StartError::ModelLoad(_) => rpc::MODEL_LOAD_FAILED,
+            };
+            Err(RpcError::new(code, e.to_string()))
+        }
+        Err(_) => Err(RpcError::new(rpc::INTERNAL_ERROR, "Failed to start")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events;
+    use crate::transcriber::Segment;
+    use tokio::io::Lines;
+    use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+
+    /// The client end of a connection served by `handle_connection`.
+    struct Connection {
+        lines: Lines<BufReader<OwnedReadHalf>>,
+        writer: OwnedWriteHalf,
+        _commands: mpsc::Receiver<Command>,
+    }
+
+    impl Connection {
+        fn open() -> Self {
+            let (client, server) = UnixStream::pair().unwrap();
+            let (cmd_tx, commands) = mpsc::channel(1);
+            tokio::spawn(handle_connection(server, cmd_tx, events::channel()));
+            let (reader, writer) = client.into_split();
+            Self {
+                lines: BufReader::new(reader).lines(),
+                writer,
+                _commands: commands,
+            }
+        }
+
+        async fn send(&mut self, text: &str) {
+            self.writer.write_all(text.as_bytes()).await.unwrap();
+        }
+
+        async fn receive(&mut self) -> Value {
+            let line = self.lines.next_line().await.unwrap().unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        /// Sends one request line and returns the error code of the reply, if any.
+        async fn error_code(&mut self, request: &Value) -> Option<i64> {
+            self.send(&format!("{}\n", request)).await;
+            self.receive().await["error"]["code"].as_i64()
+        }
+
+        async fn hello(&mut self) {
+            let hello = json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "hello",
+                "params": { "protocol_version": rpc::PROTOCOL_VERSION },
+            });
+            assert_eq!(self.error_code(&hello).await, None);
+        }
+    }
+
+    fn request(id: u64, method: &str) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method })
+    }
+
+    #[tokio::test]
+    async fn replies_line_by_line_in_order() {
+        let mut conn = Connection::open();
+        conn.hello().await;
+
+        // Two requests in one write, a blank line and a notification, which gets no reply
+        conn.send(&format!(
+            "{}\n\n{}\n{}\n",
+            request(1, "subscribe"),
+            json!({ "jsonrpc": "2.0", "method": "unsubscribe" }),
+            request(2, "unsubscribe"),
+        ))
+        .await;
+        let first = conn.receive().await;
+        assert_eq!(first["id"], 1);
+        assert_eq!(first["result"]["subscribed"], true);
+        let second = conn.receive().await;
+        assert_eq!(second["id"], 2);
+        assert_eq!(second["result"]["subscribed"], false);
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_are_told_apart() {
+        let mut conn = Connection::open();
+        conn.send("{\"jsonrpc\": \n").await;
+        let reply = conn.receive().await;
+        assert_eq!(reply["error"]["code"], rpc::PARSE_ERROR);
+        assert_eq!(reply["id"], Value::Null);
+
+        // Valid JSON, but no method
+        let reply = conn.error_code(&json!({ "jsonrpc": "2.0", "id": 7 })).await;
+        assert_eq!(reply, Some(rpc::INVALID_REQUEST.into()));
+        let reply = conn.error_code(&json!([1, 2])).await;
+        assert_eq!(reply, Some(rpc::INVALID_REQUEST.into()));
+    }
+
+    #[tokio::test]
+    async fn requests_wait_for_the_handshake() {
+        let mut conn = Connection::open();
+        let code = conn.error_code(&request(1, "status")).await;
+        assert_eq!(code, Some(rpc::HANDSHAKE_REQUIRED.into()));
+
+        let old = json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "hello",
+            "params": { "protocol_version": 0 },
+        });
+        let code = conn.error_code(&old).await;
+        assert_eq!(code, Some(rpc::UNSUPPORTED_VERSION.into()));
+        let code = conn.error_code(&request(3, "status")).await;
+        assert_eq!(code, Some(rpc::HANDSHAKE_REQUIRED.into()));
+
+        conn.hello().await;
+        let code = conn.error_code(&request(4, "transcribe")).await;
+        assert_eq!(code, Some(rpc::METHOD_NOT_FOUND.into()));
+    }
+
+    fn config_with_profile() -> SttConfig {
+        let mut config = SttConfig::default();
+        config.profiles.insert(
+            "meetings".to_string(),
+            Profile {
+                model_path: Some("ggml-medium.bin".to_string()),
+                output_mode: Some(OutputMode::Copy),
+                ..Profile::default()
+            },
+        );
+        config
+    }
+
+    #[test]
+    fn profile_overrides_only_its_fields() {
+        let base = config_with_profile();
+        let config = base.with_profile("meetings").unwrap();
+        assert_eq!(config.profile, "meetings");
+        assert_eq!(config.model_path, "ggml-medium.bin");
+        assert_eq!(config.output_mode, OutputMode::Copy);
+        assert_eq!(config.language, "es");
+        assert_eq!(config.max_recording_seconds, 600);
+
+        let config = base.with_profile(DEFAULT_PROFILE).unwrap();
+        assert_eq!(config.profile, DEFAULT_PROFILE);
+        assert_eq!(config.model_path, "ggml-base.bin");
+        assert_eq!(config.output_mode, OutputMode::Type);
+    }
+
+    #[test]
+    fn unknown_profile_is_rejected() {
+        let err = config_with_profile().with_profile("podcast").unwrap_err();
+        assert!(err.is::<UnknownProfile>());
+
+        let config = SttConfig {
+            profile: "podcast".to_string(),
+            ..config_with_profile()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn start_failures_keep_their_cause() {
+        let failures = [
+            (
+                StartError::InvalidOptions("beam_size".into()),
+                rpc::INVALID_PARAMS,
+            ),
+            (
+                StartError::Microphone("busy".into()),
+                rpc::MICROPHONE_UNAVAILABLE,
+            ),
+            (
+                StartError::ModelLoad("missing".into()),
+                rpc::MODEL_LOAD_FAILED,
+            ),
+        ];
+        for (error, code) in failures {
+            let (tx, rx) = oneshot::channel();
+            tx.send(Err(error)).unwrap();
+            assert_eq!(started(rx, "Recording").await.unwrap_err().code, code);
+        }
+    }
+
+    #[test]
+    fn file_transcripts_keep_command_phrases() {
+        let transcript = Transcript {
+            language: "en".to_string(),
+            language_probability: None,
+            segments: vec![Segment {
+                start_ms: 0,
+                end_ms: 800,
+                text: " Undo.".to_string(),
+                confidence: 0.9,
+                tokens: Vec::new(),
+            }],
+        };
+        let result = TranscriptionResult::new(transcript, 16000, 0);
+        let processor = PostProcessor::new(&PostProcessConfig::default()).unwrap();
+        let commands = CommandMatcher::new(&VoiceCommandConfig {
+            enabled: true,
+            ..VoiceCommandConfig::default()
+        });
+
+        // Live, the phrase becomes keys; from a file it is kept as text
+        let live = result.clone().finish(&commands, &processor);
+        assert!(live.text.is_empty());
+        let file = result.postprocessed(&processor);
+        assert_eq!(file.text, "Undo.");
+        assert_eq!(
+            file.action,
+            Action::Text {
+                text: "Undo.".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn deprecated_vad_keys_move_into_the_table() {
+        let mut config: SttConfig = serde_json::from_value(json!({
+            "vad_threshold": 0.02,
+            "vad_hangover_ms": 500,
+            "vad": { "snr_db": 12.0 },
+        }))
+        .unwrap();
+        config.migrate();
+        assert!((config.vad.min_level - 0.02).abs() < f32::EPSILON);
+        assert_eq!(config.vad.hangover_ms, 500);
+        assert!((config.vad.snr_db - 12.0).abs() < f32::EPSILON);
+        assert_eq!(config.vad_threshold, None);
+    }
+
+    #[test]
+    fn default_cannot_name_a_profile() {
+        let mut config = config_with_profile();
+        config
+            .profiles
+            .insert(DEFAULT_PROFILE.to_string(), Profile::default());
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("reserved"), "{}", err);
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::socket::TranscriptionResult;
+use crate::transcriber::Segment;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Srt,
+    Vtt,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "srt" => Ok(Self::Srt),
+            "vtt" | "webvtt" => Ok(Self::Vtt),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("Unknown export format: {}", s)),
+        }
+    }
+}
+
+/// Layout limits for subtitle cues.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubtitleOptions {
+    pub max_line_chars: usize,
+    pub max_lines: usize,
+    pub max_cue_ms: u64,
+}
+
+impl Default for SubtitleOptions {
+    fn default() -> Self {
+        // Common broadcast guidelines: two lines of 42 characters, at most 7 seconds
+        Self {
+            max_line_chars: 42,
+            max_lines: 2,
+            max_cue_ms: 7000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub lines: Vec<String>,
+}
+
+pub fn render(
+    result: &TranscriptionResult,
+    format: ExportFormat,
+    options: &SubtitleOptions,
+) -> Result<String> {
+    Ok(match format {
+        ExportFormat::Srt => to_srt(&result.transcript.segments, options),
+        ExportFormat::Vtt => to_vtt(&result.transcript.segments, options),
+        ExportFormat::Json => format!("{}\n", serde_json::to_string_pretty(result)?),
+    })
+}
+
+/// Renders segments as SubRip (`.srt`) cues.
+pub fn to_srt(segments: &[Segment], options: &SubtitleOptions) -> String {
+    let mut out = String::new();
+    for (i, cue) in build_cues(segments, options).iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "{}\n{} --> {}\n{}\n",
+            i + 1,
+            timestamp(cue.start_ms, ','),
+            timestamp(cue.end_ms, ','),
+            cue.lines.join("\n")
+        );
+    }
+    out
+}
+
+/// Renders segments as WebVTT (`.vtt`) cues.
+pub fn to_vtt(segments: &[Segment], options: &SubtitleOptions) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in build_cues(segments, options) {
+        let _ = writeln!(
+            out,
+            "{} --> {}\n{}\n",
+            timestamp(cue.start_ms, '.'),
+            timestamp(cue.end_ms, '.'),
+            cue.lines.join("\n")
+        );
+    }
+    out
+}
+
+/// Splits segments into cues that respect the line length, line count and duration limits.
+///
+/// Whisper only times whole segments, so word times are interpolated by character count.
+pub fn build_cues(segments: &[Segment], options: &SubtitleOptions) -> Vec<Cue> {
+    let mut cues = Vec::new();
+
+    for segment in segments {
+        let words = timed_words(segment);
+        let mut current: Vec<&TimedWord> = Vec::new();
+
+        for word in &words {
+            if let Some(first) = current.first() {
+                let too_long = wrap(&joined(&current, word), options.max_line_chars).len()
+                    > options.max_lines.max(1);
+                let too_slow = word.end_ms.saturating_sub(first.start_ms) > options.max_cue_ms;
+                if too_long || too_slow {
+                    cues.push(make_cue(&current, options));
+                    current.clear();
+                }
+            }
+            current.push(word);
+        }
+
+        if !current.is_empty() {
+            cues.push(make_cue(&current, options));
+        }
+    }
+
+    cues
+}
+
+struct TimedWord {
+    text: String,
+    start_ms: u64,
+    end_ms: u64,
+}
+
+fn timed_words(segment: &Segment) -> Vec<TimedWord> {
+    let words: Vec<&str> = segment.text.split_whitespace().collect();
+    let total_chars: usize = words.iter().map(|w| w.chars().count()).sum();
+    let duration = segment.end_ms.saturating_sub(segment.start_ms);
+
+    let mut elapsed_chars = 0;
+    words
+        .into_iter()
+        .map(|word| {
+            let start = segment.start_ms + duration * elapsed_chars as u64 / total_chars as u64;
+            elapsed_chars += word.chars().count();
+            let end = segment.start_ms + duration * elapsed_chars as u64 / total_chars as u64;
+            TimedWord {
+                text: word.to_string(),
+                start_ms: start,
+                end_ms: end,
+            }
+        })
+        .collect()
+}
+
+fn joined(current: &[&TimedWord], next: &TimedWord) -> String {
+    let mut text = current
+        .iter()
+        .map(|w| w.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    text.push(' ');
+    text.push_str(&next.text);
+    text
+}
+
+fn make_cue(words: &[&TimedWord], options: &SubtitleOptions) -> Cue {
+    let text = words
+        .iter()
+        .map(|w| w.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Cue {
+        start_ms: words[0].start_ms,
+        end_ms: words[words.len() - 1].end_ms,
+        lines: wrap(&text, options.max_line_chars),
+    }
+}
+
+/// Greedy word wrap; a single word longer than the limit gets its own line.
+fn wrap(text: &str, max_line_chars: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= max_line_chars => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+fn timestamp(ms: u64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start_ms: u64, end_ms: u64, text: &str) -> Segment {
+        Segment {
+            start_ms,
+            end_ms,
+            text: text.to_string(),
+            confidence: 0.9,
+            tokens: Vec::new(),
+        }
+    }
+
+    fn sample() -> Vec<Segment> {
+        vec![
+            segment(0, 2500, " Hola a todos."),
+            segment(
+                2500,
+                14000,
+                " Hoy vamos a revisar el estado del proyecto y las tareas pendientes para \
+                 la próxima versión del asistente de dictado.",
+            ),
+            segment(3_725_000, 3_727_040, " Gracias."),
+        ]
+    }
+
+    #[test]
+    fn srt_matches_golden() {
+        let srt = to_srt(&sample(), &SubtitleOptions::default());
+        assert_eq!(srt, include_str!("../testdata/subtitles/sample.srt"));
+    }
+
+    #[test]
+    fn vtt_matches_golden() {
+        let vtt = to_vtt(&sample(), &SubtitleOptions::default());
+        assert_eq!(vtt, include_str!("../testdata/subtitles/sample.vtt"));
+    }
+
+    #[test]
+    fn cues_respect_limits() {
+        let options = SubtitleOptions {
+            max_line_chars: 20,
+            max_lines: 2,
+            max_cue_ms: 3000,
+        };
+        for cue in build_cues(&sample(), &options) {
+            assert!(cue.lines.len() <= 2, "{:?}", cue);
+            assert!(
+                cue.lines.iter().all(|l| l.chars().count() <= 20),
+                "{:?}",
+                cue
+            );
+            assert!(cue.end_ms - cue.start_ms <= 3000, "{:?}", cue);
+        }
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!("SRT".parse::<ExportFormat>().unwrap(), ExportFormat::Srt);
+        assert_eq!("webvtt".parse::<ExportFormat>().unwrap(), ExportFormat::Vtt);
+        assert!("txt".parse::<ExportFormat>().is_err());
+    }
+}
@@ -1,61 +1,437 @@
 use anyhow::{Context, Result, anyhow};
-use log::info;
-use std::path::Path;
-use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use crate::mock_engine::MockEngine;
+use crate::whisper_engine::WhisperEngine;
+
+/// Language value that asks whisper to detect the spoken language.
+pub const AUTO_LANGUAGE: &str = "auto";
+
+/// How a recording should be decoded.
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// Language code such as "es", or "auto" to detect it
+    pub language: String,
+    /// When detecting, only these languages are considered (all if empty)
+    pub language_candidates: Vec<String>,
+    pub decoding: DecodingParams,
+    /// Terms from the vocabulary file
+    pub vocabulary: Vec<String>,
+    /// Prompt text sent with this request only
+    pub hint: Option<String>,
+    /// Set by CANCEL to stop the decode early
+    pub cancel: CancelToken,
+}
+
+/// Shared flag that asks the decodes of a recording to stop.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// The flag itself, for C callbacks; valid while this token is alive.
+    pub fn as_ptr(&self) -> *const AtomicBool {
+        Arc::as_ptr(&self.0)
+    }
+}
+
+impl DecodeOptions {
+    /// Initial prompt combining the vocabulary, the configured prompt and the request hint.
+    ///
+    /// Whisper keeps only the end of long prompts, so the most specific text goes last.
+    pub fn prompt(&self) -> String {
+        let vocabulary = self.vocabulary.join(", ");
+        [
+            vocabulary.as_str(),
+            self.decoding.initial_prompt.as_str(),
+            self.hint.as_deref().unwrap_or_default(),
+        ]
+        .iter()
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('\0', "")
+    }
+}
+
+#[cfg(test)]
+impl DecodeOptions {
+    /// Default decoding in `language`, with no vocabulary or hint.
+    pub fn for_test(language: &str) -> Self {
+        Self {
+            language: language.to_string(),
+            language_candidates: Vec::new(),
+            decoding: DecodingParams::default(),
+            vocabulary: Vec::new(),
+            hint: None,
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+/// Decoder settings, read from the `[decoding]` config table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecodingParams {
+    /// Inference threads; 0 picks one per core, up to 8
+    pub threads: usize,
+    /// Sampling temperature; 0 is deterministic
+    pub temperature: f32,
+    /// Beams for beam search; 1 decodes greedily
+    pub beam_size: u32,
+    /// Candidates sampled when decoding greedily at a non-zero temperature
+    pub best_of: u32,
+    /// Text whisper treats as preceding the audio, used to bias spelling and style
+    pub initial_prompt: String,
+    /// Segments more likely than this to be silence are dropped
+    pub no_speech_threshold: f32,
+    pub suppress_blank: bool,
+    /// Drop tokens such as "[Music]" or "(laughs)"
+    pub suppress_non_speech_tokens: bool,
+}
+
+impl Default for DecodingParams {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            temperature: 0.0,
+            beam_size: 1,
+            best_of: 1,
+            initial_prompt: String::new(),
+            no_speech_threshold: 0.6,
+            suppress_blank: true,
+            suppress_non_speech_tokens: false,
+        }
+    }
+}
+
+impl DecodingParams {
+    pub fn validate(&self) -> Result<()> {
+        if self.threads > 64 {
+            return Err(anyhow!("threads must be at most 64, got {}", self.threads));
+        }
+        if !(0.0..=1.0).contains(&self.temperature) {
+            return Err(anyhow!(
+                "temperature must be between 0 and 1, got {}",
+                self.temperature
+            ));
+        }
+        if !(1..=16).contains(&self.beam_size) {
+            return Err(anyhow!(
+                "beam_size must be between 1 and 16, got {}",
+                self.beam_size
+            ));
+        }
+        if !(1..=16).contains(&self.best_of) {
+            return Err(anyhow!(
+                "best_of must be between 1 and 16, got {}",
+                self.best_of
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.no_speech_threshold) {
+            return Err(anyhow!(
+                "no_speech_threshold must be between 0 and 1, got {}",
+                self.no_speech_threshold
+            ));
+        }
+        if self.initial_prompt.contains('\0') {
+            return Err(anyhow!("initial_prompt must not contain NUL characters"));
+        }
+        Ok(())
+    }
+}
+
+/// Per-request changes to the configured `DecodingParams`; unset fields keep their value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecodingOverrides {
+    pub threads: Option<usize>,
+    pub temperature: Option<f32>,
+    pub beam_size: Option<u32>,
+    pub best_of: Option<u32>,
+    pub initial_prompt: Option<String>,
+    pub no_speech_threshold: Option<f32>,
+    pub suppress_blank: Option<bool>,
+    pub suppress_non_speech_tokens: Option<bool>,
+}
+
+impl DecodingOverrides {
+    /// Applies the overrides on top of `base` and validates the result.
+    pub fn apply(&self, base: &DecodingParams) -> Result<DecodingParams> {
+        let params = DecodingParams {
+            threads: self.threads.unwrap_or(base.threads),
+            temperature: self.temperature.unwrap_or(base.temperature),
+            beam_size: self.beam_size.unwrap_or(base.beam_size),
+            best_of: self.best_of.unwrap_or(base.best_of),
+            initial_prompt: self
+                .initial_prompt
+                .clone()
+                .unwrap_or_else(|| base.initial_prompt.clone()),
+            no_speech_threshold: self.no_speech_threshold.unwrap_or(base.no_speech_threshold),
+            suppress_blank: self.suppress_blank.unwrap_or(base.suppress_blank),
+            suppress_non_speech_tokens: self
+                .suppress_non_speech_tokens
+                .unwrap_or(base.suppress_non_speech_tokens),
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+/// A decoded token and the probability whisper assigned to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Token {
+    pub text: String,
+    pub probability: f32,
+}
+
+/// A piece of transcribed text and its position in the input, in milliseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+    /// Mean token probability, from 0 to 1
+    pub confidence: f32,
+    #[serde(default)]
+    pub tokens: Vec<Token>,
+}
+
+/// Structured result of a transcription.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Transcript {
+    /// Language whisper decoded in, as a code such as "es"
+    pub language: String,
+    /// Detection probability of `language`, when it was detected automatically
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language_probability: Option<f32>,
+    pub segments: Vec<Segment>,
+}
+
+impl Transcript {
+    /// The plain text of all segments.
+    pub fn text(&self) -> String {
+        join_segments(&self.segments)
+    }
+}
+
+/// What an engine can do beyond plain transcription.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Can honour `language = "auto"`
+    pub language_detection: bool,
+    /// Fills in per-token probabilities and segment confidences
+    pub token_probabilities: bool,
+}
+
+/// A speech-to-text backend.
+pub trait SpeechEngine: Send {
+    fn name(&self) -> &'static str;
+    /// Checks that `model_path` is there to load, without loading it. By default the
+    /// file must be readable.
+    fn check(&self, model_path: &str) -> Result<()> {
+        std::fs::File::open(model_path)
+            .with_context(|| format!("Cannot read model {}", model_path))?;
+        Ok(())
+    }
+    fn load(&mut self, model_path: &str) -> Result<()>;
+    fn transcribe(&mut self, audio: &[f32], options: &DecodeOptions) -> Result<Transcript>;
+    /// Compute backend the loaded model runs on, such as "cpu" or "cuda"
+    fn backend(&self) -> &'static str {
+        "cpu"
+    }
+    fn capabilities(&self) -> Capabilities;
+    /// Releases the model; `load` must be called again before transcribing.
+    fn unload(&mut self);
+}
+
+/// Creates an unloaded engine by its config name.
+pub fn create_engine(name: &str) -> Result<Box<dyn SpeechEngine>> {
+    match name {
+        "whisper" => Ok(Box::new(WhisperEngine::default())),
+        "mock" => Ok(Box::new(MockEngine)),
+        _ => Err(anyhow!("Unknown speech engine: {}", name)),
+    }
+}
+
+/// The loaded engine used by the daemon.
 pub struct Transcriber {
-    ctx: WhisperContext,
+    engine: Box<dyn SpeechEngine>,
 }
 
 impl Transcriber {
-    pub fn new(model_path: &str) -> Result<Self> {
-        if !Path::new(model_path).exists() {
-            return Err(anyhow!("Model file not found: {}", model_path));
-        }
+    pub fn new(engine: &str, model_path: &str) -> Result<Self> {
+        let mut engine = create_engine(engine)?;
+        engine.load(model_path)?;
+        Ok(Self::from_engine(engine))
+    }
+
+    /// Wraps an engine that has already been loaded.
+    pub fn from_engine(engine: Box<dyn SpeechEngine>) -> Self {
+        Self { engine }
+    }
 
-        info!("Loading Whisper model from {}...", model_path);
+    pub fn engine_name(&self) -> &'static str {
+        self.engine.name()
+    }
+
+    pub fn backend(&self) -> &'static str {
+        self.engine.backend()
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.engine.capabilities()
+    }
 
-        // Load the model
-        let ctx = WhisperContext::new_with_params(model_path, WhisperContextParameters::default())
-            .context("Failed to load Whisper model")?;
+    pub fn transcribe(
+        &mut self,
+        audio_data: &[f32],
+        options: &DecodeOptions,
+    ) -> Result<Transcript> {
+        if options.language == AUTO_LANGUAGE && !self.capabilities().language_detection {
+            // Fall back to the first candidate rather than failing the recording
+            let language = options
+                .language_candidates
+                .first()
+                .cloned()
+                .unwrap_or_else(|| "en".to_string());
+            warn!(
+                "{} engine cannot detect the language, using {}",
+                self.engine_name(),
+                language
+            );
+            let options = DecodeOptions {
+                language,
+                ..options.clone()
+            };
+            return self.engine.transcribe(audio_data, &options);
+        }
+        self.engine.transcribe(audio_data, options)
+    }
+}
+
+impl Drop for Transcriber {
+    fn drop(&mut self) {
+        self.engine.unload();
+    }
+}
 
-        info!("Whisper model loaded successfully.");
-        Ok(Self { ctx })
+pub fn join_segments(segments: &[Segment]) -> String {
+    let mut text = String::new();
+    for segment in segments {
+        text.push_str(&segment.text);
+        text.push(' ');
     }
+    text.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn transcribe(&mut self, audio_data: &[f32], language: Option<&str>) -> Result<String> {
-        let mut state = self
-            .ctx
-            .create_state()
-            .context("Failed to create Whisper state")?;
+    fn options(vocabulary: &[&str], initial_prompt: &str, hint: Option<&str>) -> DecodeOptions {
+        DecodeOptions {
+            decoding: DecodingParams {
+                initial_prompt: initial_prompt.to_string(),
+                ..DecodingParams::default()
+            },
+            vocabulary: vocabulary.iter().map(ToString::to_string).collect(),
+            hint: hint.map(str::to_string),
+            ..DecodeOptions::for_test("en")
+        }
+    }
 
-        // Configure parameters
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-        params.set_language(Some(language.unwrap_or("es")));
-        params.set_print_special(false);
-        params.set_print_progress(false);
-        params.set_print_realtime(false);
-        params.set_print_timestamps(false);
+    #[test]
+    fn prompt_puts_the_hint_last() {
+        let options = options(
+            &["Telora", "whisper.cpp"],
+            "Meeting notes.",
+            Some(" Nuria, Oriol "),
+        );
+        assert_eq!(
+            options.prompt(),
+            "Telora, whisper.cpp Meeting notes. Nuria, Oriol"
+        );
+    }
 
-        // Run the transcription
-        state
-            .full(params, audio_data)
-            .context("Failed to run full transcription")?;
+    #[test]
+    fn prompt_skips_empty_parts() {
+        assert_eq!(options(&[], "", None).prompt(), "");
+        assert_eq!(options(&["Telora"], "  ", Some("")).prompt(), "Telora");
+        assert_eq!(options(&[], "", Some("Nuria\0")).prompt(), "Nuria");
+    }
 
-        // Fetch the results
-        let num_segments = state
-            .full_n_segments()
-            .context("Failed to get number of segments")?;
-        let mut text = String::new();
+    #[test]
+    fn decoding_params_are_range_checked() {
+        assert!(DecodingParams::default().validate().is_ok());
 
-        for i in 0..num_segments {
-            let segment = state
-                .full_get_segment_text(i)
-                .context("Failed to get segment text")?;
-            text.push_str(&segment);
-            text.push(' ');
+        let invalid = [
+            DecodingParams {
+                threads: 65,
+                ..DecodingParams::default()
+            },
+            DecodingParams {
+                temperature: 1.5,
+                ..DecodingParams::default()
+            },
+            DecodingParams {
+                beam_size: 0,
+                ..DecodingParams::default()
+            },
+            DecodingParams {
+                best_of: 17,
+                ..DecodingParams::default()
+            },
+            DecodingParams {
+                no_speech_threshold: -0.1,
+                ..DecodingParams::default()
+            },
+            DecodingParams {
+                initial_prompt: "a\0b".to_string(),
+                ..DecodingParams::default()
+            },
+        ];
+        for params in invalid {
+            assert!(params.validate().is_err(), "{:?}", params);
         }
+    }
+
+    #[test]
+    fn overrides_replace_only_what_they_set() {
+        let base = DecodingParams {
+            threads: 4,
+            initial_prompt: "Meeting notes.".to_string(),
+            ..DecodingParams::default()
+        };
+        let overrides = DecodingOverrides {
+            beam_size: Some(5),
+            suppress_blank: Some(false),
+            ..DecodingOverrides::default()
+        };
+
+        let params = overrides.apply(&base).unwrap();
+        assert_eq!(params.beam_size, 5);
+        assert!(!params.suppress_blank);
+        assert_eq!(params.threads, 4);
+        assert_eq!(params.initial_prompt, "Meeting notes.");
+        assert!((params.no_speech_threshold - 0.6).abs() < f32::EPSILON);
 
-        Ok(text.trim().to_string())
+        // The merged result is validated
+        let overrides = DecodingOverrides {
+            temperature: Some(2.0),
+            ..DecodingOverrides::default()
+        };
+        assert!(overrides.apply(&base).is_err());
     }
 }
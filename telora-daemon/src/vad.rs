@@ -1,44 +1,709 @@
-#[allow(dead_code)]
+use anyhow::{Result, anyhow};
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::resample::TARGET_SAMPLE_RATE;
+
+/// Samples per decision: 32 ms at 16 kHz, the window Silero models expect.
+pub const FRAME: usize = 512;
+const FRAME_MS: u32 = (FRAME as u32 * 1000) / TARGET_SAMPLE_RATE;
+// Speech frames in a row needed to open a segment, so single clicks do not
+const ONSET_FRAMES: usize = 2;
+
+/// Settings for the `[vad]` config table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VadConfig {
+    pub backend: VadBackend,
+    /// RMS level below which audio never counts as speech, whatever the noise floor
+    /// ("energy" only)
+    pub min_level: f32,
+    /// Silence needed after speech before a segment closes, in milliseconds
+    pub hangover_ms: u32,
+    /// Silero ONNX model, for `backend = "silero"`
+    pub model_path: String,
+    /// How far above the background noise speech must rise, in dB
+    pub snr_db: f32,
+    /// Model output above which a frame counts as speech
+    pub speech_probability: f32,
+    /// Cut the silence before and after the speech in a recording before transcribing it
+    pub trim_silence: bool,
+    /// Audio kept on each side of the speech when trimming, in milliseconds
+    pub trim_padding_ms: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            backend: VadBackend::Energy,
+            min_level: 0.01,
+            hangover_ms: 800,
+            model_path: String::new(),
+            snr_db: 9.0,
+            speech_probability: 0.5,
+            trim_silence: true,
+            trim_padding_ms: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VadBackend {
+    /// Level over an adaptive noise floor, zero crossings, spectral balance and pitch
+    #[default]
+    Energy,
+    /// A Silero model run through ONNX Runtime
+    Silero,
+}
+
+impl VadConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..1.0).contains(&self.min_level) {
+            return Err(anyhow!(
+                "min_level must be at least 0 and below 1, got {}",
+                self.min_level
+            ));
+        }
+        if !(0.0..=40.0).contains(&self.snr_db) {
+            return Err(anyhow!(
+                "snr_db must be between 0 and 40, got {}",
+                self.snr_db
+            ));
+        }
+        if !(0.0..1.0).contains(&self.speech_probability) {
+            return Err(anyhow!(
+                "speech_probability must be at least 0 and below 1, got {}",
+                self.speech_probability
+            ));
+        }
+        if self.backend == VadBackend::Silero {
+            if !cfg!(feature = "silero") {
+                return Err(anyhow!(
+                    "backend \"silero\" needs telora-daemon built with the silero feature"
+                ));
+            }
+            if self.model_path.is_empty() {
+                return Err(anyhow!("backend \"silero\" needs a model_path"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Classifies one frame of 16 kHz audio.
+trait Detector {
+    fn is_speech(&mut self, frame: &[f32]) -> bool;
+    fn reset(&mut self);
+}
+
+/// Voice activity detection on a 16 kHz stream. Audio is judged in 32 ms frames;
+/// a segment opens after two speech frames in a row and closes once the hangover
+/// has passed without speech.
 pub struct Vad {
-    threshold: f32,
-    hold_frames: usize,
-    silence_counter: usize,
+    detector: Box<dyn Detector>,
+    /// Samples waiting for a full frame
+    frame: Vec<f32>,
+    hangover_frames: usize,
+    speech_run: usize,
+    silence_run: usize,
     is_speaking: bool,
-    sample_rate: u32,
+    /// Samples judged since the last reset
+    position: usize,
+    /// From the first to the last speech frame seen since the last reset
+    span: Option<Range<usize>>,
+    /// Samples in speech frames since the last reset
+    speech: usize,
 }
 
-#[allow(dead_code)]
 impl Vad {
-    pub fn new(threshold: f32, hold_ms: u64, sample_rate: u32) -> Self {
-        Self {
-            threshold,
-            hold_frames: (hold_ms as f32 / 20.0) as usize, // Assuming ~20ms chunks logic roughly
-            silence_counter: 0,
+    pub fn new(config: &VadConfig) -> Result<Self> {
+        let detector: Box<dyn Detector> = match config.backend {
+            VadBackend::Energy => Box::new(EnergyDetector::new(config.min_level, config.snr_db)),
+            #[cfg(feature = "silero")]
+            VadBackend::Silero => Box::new(silero::SileroDetector::load(
+                &config.model_path,
+                config.speech_probability,
+            )?),
+            #[cfg(not(feature = "silero"))]
+            VadBackend::Silero => {
+                return Err(anyhow!(
+                    "backend \"silero\" needs telora-daemon built with the silero feature"
+                ));
+            }
+        };
+        Ok(Self {
+            detector,
+            frame: Vec::with_capacity(FRAME),
+            hangover_frames: config.hangover_ms.div_ceil(FRAME_MS) as usize,
+            speech_run: 0,
+            silence_run: 0,
             is_speaking: false,
-            sample_rate,
-        }
+            position: 0,
+            span: None,
+            speech: 0,
+        })
     }
 
-    // Simple RMS calculation
-    fn calculate_rms(samples: &[f32]) -> f32 {
-        let sum_squares: f32 = samples.iter().map(|s| s * s).sum();
-        (sum_squares / samples.len() as f32).sqrt()
+    pub fn reset(&mut self) {
+        self.detector.reset();
+        self.frame.clear();
+        self.speech_run = 0;
+        self.silence_run = 0;
+        self.is_speaking = false;
+        self.position = 0;
+        self.span = None;
+        self.speech = 0;
     }
 
+    /// Feeds audio and says whether a speech segment is open after it.
     pub fn is_voice_segment(&mut self, samples: &[f32]) -> bool {
-        let rms = Vad::calculate_rms(samples);
+        let mut rest = samples;
+        while !rest.is_empty() {
+            let take = (FRAME - self.frame.len()).min(rest.len());
+            self.frame.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.frame.len() == FRAME {
+                let speech = self.detector.is_speech(&self.frame);
+                self.frame.clear();
+                self.judge(speech);
+            }
+        }
+        self.is_speaking
+    }
 
-        if rms > self.threshold {
+    fn judge(&mut self, speech: bool) {
+        self.position += FRAME;
+        self.speech_run = if speech { self.speech_run + 1 } else { 0 };
+
+        if self.is_speaking {
+            if speech {
+                self.silence_run = 0;
+                self.extend_span(1);
+            } else {
+                self.silence_run += 1;
+                if self.silence_run >= self.hangover_frames {
+                    self.is_speaking = false;
+                }
+            }
+        } else if self.speech_run >= ONSET_FRAMES {
             self.is_speaking = true;
-            self.silence_counter = 0;
-        } else if self.is_speaking {
-            self.silence_counter += 1;
-            if self.silence_counter > 20 {
-                // 20 * chunk_duration wait
-                self.is_speaking = false;
+            self.silence_run = 0;
+            self.extend_span(self.speech_run);
+        }
+    }
+
+    /// Adds the last `frames` frames to the speech span.
+    fn extend_span(&mut self, frames: usize) {
+        self.speech += frames * FRAME;
+        let start = self.position - frames * FRAME;
+        match &mut self.span {
+            Some(span) => span.end = self.position,
+            None => self.span = Some(start..self.position),
+        }
+    }
+
+    /// Where speech was heard since the last reset, in samples.
+    pub fn speech_span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+
+    /// How much speech was heard since the last reset, in samples.
+    pub fn speech_samples(&self) -> usize {
+        self.speech
+    }
+
+    /// Samples judged since the last speech frame, or `None` before any speech.
+    pub fn trailing_silence(&self) -> Option<usize> {
+        self.span.as_ref().map(|span| self.position - span.end)
+    }
+}
+
+/// Widens a speech span by `padding` samples on each side, within `len` samples.
+pub fn padded(span: &Range<usize>, padding: usize, len: usize) -> Range<usize> {
+    span.start.saturating_sub(padding).min(len)..(span.end + padding).min(len)
+}
+
+// Level of the noise floor before any audio has been heard, in dBFS
+const INITIAL_FLOOR_DB: f32 = -60.0;
+// How fast the floor follows quieter audio, and louder audio that is not speech
+const FLOOR_FALL: f32 = 0.3;
+const FLOOR_RISE: f32 = 0.05;
+// Most the floor climbs per frame of speech, so steady noise mistaken for speech
+// is absorbed within a few seconds while real speech pauses often enough
+const FLOOR_CREEP_DB: f32 = 0.1;
+// Zero crossings per sample above which a frame is hiss rather than voice
+const MAX_ZCR: f32 = 0.3;
+// Share of the energy that must fall in the voice band
+const MIN_VOICE_BAND: f32 = 0.6;
+const VOICE_BAND_HZ: Range<f32> = 250.0..4000.0;
+// Correlation with itself one pitch period later that marks voiced sound
+const MIN_PERIODICITY: f32 = 0.6;
+const PITCH_HZ: Range<u32> = 60..400;
+
+/// Speech is louder than the noise floor by the margin, crosses zero at voice
+/// rates, has most of its energy in the voice band and repeats at a voice pitch.
+struct EnergyDetector {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    voice_bins: Range<usize>,
+    threshold_db: f32,
+    snr_db: f32,
+    floor_db: f32,
+}
+
+impl EnergyDetector {
+    fn new(threshold: f32, snr_db: f32) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FRAME);
+        let spectrum = fft.make_output_vec();
+        let bin_hz = TARGET_SAMPLE_RATE as f32 / FRAME as f32;
+        let window = (0..FRAME)
+            .map(|i| {
+                (std::f32::consts::PI * i as f32 / FRAME as f32)
+                    .sin()
+                    .powi(2)
+            })
+            .collect();
+        Self {
+            fft,
+            window,
+            buffer: vec![0.0; FRAME],
+            spectrum,
+            voice_bins: (VOICE_BAND_HZ.start / bin_hz) as usize
+                ..(VOICE_BAND_HZ.end / bin_hz) as usize,
+            threshold_db: 20.0 * threshold.max(1e-6).log10(),
+            snr_db,
+            floor_db: INITIAL_FLOOR_DB,
+        }
+    }
+
+    /// Share of the frame's energy in the voice band.
+    fn voice_share(&mut self, frame: &[f32]) -> Option<f32> {
+        for ((slot, s), w) in self.buffer.iter_mut().zip(frame).zip(&self.window) {
+            *slot = s * w;
+        }
+        self.fft
+            .process(&mut self.buffer, &mut self.spectrum)
+            .ok()?;
+
+        let power = |c: &Complex<f32>| c.norm_sqr() + 1e-12;
+        let total: f32 = self.spectrum[1..].iter().map(power).sum();
+        let band: f32 = self.spectrum[self.voice_bins.clone()]
+            .iter()
+            .map(power)
+            .sum();
+        Some(band / total)
+    }
+}
+
+/// The strongest normalized autocorrelation at a lag of one voice pitch period.
+fn periodicity(frame: &[f32]) -> f32 {
+    let lags = (TARGET_SAMPLE_RATE / PITCH_HZ.end) as usize
+        ..=(TARGET_SAMPLE_RATE / PITCH_HZ.start) as usize;
+    lags.filter(|lag| *lag < frame.len())
+        .map(|lag| {
+            let (a, b) = (&frame[..frame.len() - lag], &frame[lag..]);
+            let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+            let energy = |s: &[f32]| s.iter().map(|x| x * x).sum::<f32>();
+            dot / ((energy(a) * energy(b)).sqrt() + 1e-12)
+        })
+        .fold(0.0, f32::max)
+}
+
+impl Detector for EnergyDetector {
+    fn is_speech(&mut self, frame: &[f32]) -> bool {
+        let energy = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+        let level_db = 10.0 * (energy + 1e-12).log10();
+        let loud = level_db > self.threshold_db && level_db > self.floor_db + self.snr_db;
+
+        let speech = loud && {
+            let crossings = frame
+                .windows(2)
+                .filter(|w| (w[0] >= 0.0) != (w[1] >= 0.0))
+                .count();
+            let zcr = crossings as f32 / (frame.len() - 1) as f32;
+            zcr < MAX_ZCR
+                && self
+                    .voice_share(frame)
+                    .is_some_and(|share| share >= MIN_VOICE_BAND)
+                && periodicity(frame) >= MIN_PERIODICITY
+        };
+
+        let delta = level_db - self.floor_db;
+        self.floor_db += if delta < 0.0 {
+            delta * FLOOR_FALL
+        } else if speech {
+            delta.min(FLOOR_CREEP_DB)
+        } else {
+            delta * FLOOR_RISE
+        };
+        speech
+    }
+
+    fn reset(&mut self) {
+        self.floor_db = INITIAL_FLOOR_DB;
+    }
+}
+
+#[cfg(feature = "silero")]
+mod silero {
+    use anyhow::{Context, Result};
+    use log::error;
+    use ort::session::Session;
+    use ort::value::Tensor;
+
+    use super::{Detector, FRAME};
+    use crate::resample::TARGET_SAMPLE_RATE;
+
+    // Samples of the previous frame the model sees along with each new one
+    const CONTEXT: usize = 64;
+    const STATE: usize = 2 * 128;
+
+    /// Silero VAD (v5), a small recurrent network that scores each frame.
+    pub struct SileroDetector {
+        session: Session,
+        threshold: f32,
+        input: Vec<f32>,
+        state: Vec<f32>,
+    }
+
+    impl SileroDetector {
+        pub fn load(path: &str, threshold: f32) -> Result<Self> {
+            let session = Session::builder()
+                .and_then(|b| b.commit_from_file(path))
+                .with_context(|| format!("Failed to load VAD model {}", path))?;
+            Ok(Self {
+                session,
+                threshold,
+                input: vec![0.0; CONTEXT + FRAME],
+                state: vec![0.0; STATE],
+            })
+        }
+
+        fn probability(&mut self, frame: &[f32]) -> Result<f32> {
+            self.input.copy_within(FRAME.., 0);
+            self.input[CONTEXT..].copy_from_slice(frame);
+
+            let input = Tensor::from_array(([1, CONTEXT + FRAME], self.input.clone()))?;
+            let state = Tensor::from_array(([2, 1, 128], self.state.clone()))?;
+            let rate = Tensor::from_array(([1], vec![i64::from(TARGET_SAMPLE_RATE)]))?;
+            let outputs = self.session.run(ort::inputs![
+                "input" => input,
+                "state" => state,
+                "sr" => rate,
+            ])?;
+
+            let (_, state) = outputs["stateN"].try_extract_tensor::<f32>()?;
+            self.state.copy_from_slice(state);
+            let (_, probability) = outputs["output"].try_extract_tensor::<f32>()?;
+            Ok(probability.first().copied().unwrap_or_default())
+        }
+    }
+
+    impl Detector for SileroDetector {
+        fn is_speech(&mut self, frame: &[f32]) -> bool {
+            match self.probability(frame) {
+                Ok(p) => p > self.threshold,
+                Err(e) => {
+                    error!("VAD model failed: {:#}", e);
+                    false
+                }
             }
         }
 
-        self.is_speaking
+        fn reset(&mut self) {
+            self.input.fill(0.0);
+            self.state.fill(0.0);
+        }
+    }
+}
+
+/// Synthetic clips with known speech, standing in for recordings in tests.
+#[cfg(test)]
+pub mod corpus {
+    use std::f32::consts::TAU;
+    use std::ops::Range;
+
+    const RATE: f32 = 16000.0;
+
+    pub struct Clip {
+        pub name: &'static str,
+        pub samples: Vec<f32>,
+        /// Where the speech is, in samples
+        pub speech: Vec<Range<usize>>,
+    }
+
+    fn samples(seconds: f32) -> usize {
+        (seconds * RATE) as usize
+    }
+
+    /// Uniform noise from a fixed seed, so every run sees the same clip.
+    pub fn white(amplitude: f32, seconds: f32, seed: u32) -> Vec<f32> {
+        let mut state = seed;
+        (0..samples(seconds))
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                amplitude * ((state >> 8) as f32 / (1 << 23) as f32 - 1.0)
+            })
+            .collect()
+    }
+
+    /// Low-frequency rumble, like a fan or a car: white noise through a one-pole low-pass.
+    fn rumble(amplitude: f32, seconds: f32) -> Vec<f32> {
+        let mut y = 0.0;
+        white(amplitude * 8.0, seconds, 99)
+            .into_iter()
+            .map(|x| {
+                y += 0.05 * (x - y);
+                y
+            })
+            .collect()
+    }
+
+    /// Mains hum at 50 Hz and its first harmonics.
+    fn hum(amplitude: f32, seconds: f32) -> Vec<f32> {
+        (0..samples(seconds))
+            .map(|i| {
+                let t = i as f32 / RATE;
+                amplitude * ((TAU * 50.0 * t).sin() + 0.5 * (TAU * 100.0 * t).sin())
+            })
+            .collect()
+    }
+
+    /// Sharp taps four times a second, like typing.
+    fn clicks(amplitude: f32, seconds: f32) -> Vec<f32> {
+        let mut out = vec![0.0; samples(seconds)];
+        for start in (0..out.len()).step_by(4000) {
+            for (i, s) in out[start..].iter_mut().take(40).enumerate() {
+                *s = amplitude * if i % 2 == 0 { 1.0 } else { -1.0 } * (1.0 - i as f32 / 40.0);
+            }
+        }
+        out
+    }
+
+    /// Voiced syllables: a gliding pitch with harmonics shaped by two formants,
+    /// under a syllable-rate envelope. RMS is about `level`.
+    pub fn speech(level: f32, seconds: f32) -> Vec<f32> {
+        let syllable = 0.25;
+        let mut phase = 0.0f32;
+        (0..samples(seconds))
+            .map(|i| {
+                let t = i as f32 / RATE;
+                let f0 = 130.0 + 40.0 * (TAU * 0.7 * t).sin();
+                phase += TAU * f0 / RATE;
+                let formants = [(700.0, 1.0), (1200.0, 0.6), (2600.0, 0.2)];
+                let voice: f32 = (1..30)
+                    .map(|k| {
+                        let f = k as f32 * f0;
+                        let gain: f32 = formants
+                            .iter()
+                            .map(|(center, g)| g * (-((f - center) / 250.0).powi(2)).exp())
+                            .sum();
+                        gain * (k as f32 * phase).sin()
+                    })
+                    .sum();
+                // Never quite silent between syllables, as in running speech
+                let envelope = 0.3 + 0.7 * (std::f32::consts::PI * t / syllable).sin().powi(2);
+                level * 1.4 * voice * envelope
+            })
+            .collect()
+    }
+
+    fn silence(seconds: f32) -> Vec<f32> {
+        white(0.0003, seconds, 7)
+    }
+
+    /// Background, with `speech` added from `at` seconds on.
+    fn mix(background: Vec<f32>, speech: &[f32], at: f32) -> (Vec<f32>, Range<usize>) {
+        let start = samples(at);
+        let mut out = background;
+        for (s, v) in out[start..].iter_mut().zip(speech) {
+            *s += v;
+        }
+        (out, start..start + speech.len())
+    }
+
+    pub fn clips() -> Vec<Clip> {
+        let noise_only = |name, samples| Clip {
+            name,
+            samples,
+            speech: Vec::new(),
+        };
+        let with_speech = |name, (samples, speech): (Vec<f32>, Range<usize>)| Clip {
+            name,
+            samples,
+            speech: vec![speech],
+        };
+        let words = speech(0.1, 1.5);
+
+        let mut two = mix(silence(5.0), &words, 0.5);
+        let second = mix(two.0, &speech(0.05, 1.0), 3.5);
+        two.0 = second.0;
+
+        vec![
+            noise_only("silence", silence(3.0)),
+            noise_only("white noise", white(0.05, 3.0, 11)),
+            noise_only("rumble", rumble(0.05, 3.0)),
+            noise_only("hum", hum(0.05, 3.0)),
+            noise_only("clicks", clicks(0.5, 3.0)),
+            with_speech("speech in quiet", mix(silence(3.5), &words, 1.0)),
+            with_speech(
+                "speech in white noise",
+                mix(white(0.03, 4.0, 5), &words, 2.0),
+            ),
+            with_speech("speech over rumble", mix(rumble(0.03, 4.0), &words, 2.0)),
+            with_speech("speech over hum", mix(hum(0.02, 4.0), &words, 2.0)),
+            Clip {
+                name: "two utterances",
+                samples: two.0,
+                speech: vec![two.1, second.1],
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::corpus::{self, Clip};
+    use super::*;
+
+    const HANGOVER_MS: u32 = 300;
+
+    fn vad() -> Vad {
+        Vad::new(&VadConfig {
+            hangover_ms: HANGOVER_MS,
+            ..VadConfig::default()
+        })
+        .unwrap()
+    }
+
+    /// The segment state after every frame of the clip.
+    fn decisions(vad: &mut Vad, clip: &Clip) -> Vec<bool> {
+        clip.samples
+            .chunks(FRAME)
+            .map(|frame| vad.is_voice_segment(frame))
+            .collect()
+    }
+
+    #[test]
+    fn noise_is_not_speech() {
+        for clip in corpus::clips().iter().filter(|c| c.speech.is_empty()) {
+            let mut vad = vad();
+            let frames = decisions(&mut vad, clip);
+            let flagged = frames.iter().filter(|s| **s).count();
+            assert_eq!(
+                flagged, 0,
+                "{}: {} frames taken for speech",
+                clip.name, flagged
+            );
+            assert_eq!(vad.speech_span(), None, "{}", clip.name);
+        }
+    }
+
+    #[test]
+    fn speech_is_found_in_every_background() {
+        let tolerance = 4 * FRAME;
+        let hangover = HANGOVER_MS as usize * 16;
+        for clip in corpus::clips().iter().filter(|c| !c.speech.is_empty()) {
+            let mut vad = vad();
+            let frames = decisions(&mut vad, clip);
+
+            let in_speech = |frame: usize| {
+                let at = frame * FRAME;
+                clip.speech
+                    .iter()
+                    .any(|s| at + tolerance >= s.start && at < s.end + hangover + tolerance)
+            };
+            let speech_frames: Vec<_> = (0..frames.len())
+                .filter(|i| {
+                    clip.speech
+                        .iter()
+                        .any(|s| (s.start..s.end).contains(&(i * FRAME)))
+                })
+                .collect();
+            let found = speech_frames.iter().filter(|i| frames[**i]).count();
+            assert!(
+                found * 10 >= speech_frames.len() * 9,
+                "{}: found {} of {} speech frames",
+                clip.name,
+                found,
+                speech_frames.len()
+            );
+            let false_alarms = (0..frames.len())
+                .filter(|i| frames[*i] && !in_speech(*i))
+                .count();
+            assert_eq!(false_alarms, 0, "{}", clip.name);
+
+            let span = vad.speech_span().unwrap();
+            let first = clip.speech.first().unwrap().start;
+            let last = clip.speech.last().unwrap().end;
+            assert!(
+                span.start.abs_diff(first) <= tolerance,
+                "{}: {:?}",
+                clip.name,
+                span
+            );
+            assert!(
+                span.end.abs_diff(last) <= tolerance,
+                "{}: {:?}",
+                clip.name,
+                span
+            );
+        }
+    }
+
+    #[test]
+    fn hangover_is_counted_in_milliseconds() {
+        let words = corpus::speech(0.1, 1.0);
+        for chunk in [160, 512, 2000] {
+            let mut vad = vad();
+            for piece in words.chunks(chunk) {
+                vad.is_voice_segment(piece);
+            }
+            let silence = vec![0.0; 16];
+            let mut after = 0;
+            while vad.is_voice_segment(&silence) {
+                after += silence.len();
+            }
+            // Closes on the frame boundary at or after 300 ms of silence
+            let ms = after / 16;
+            assert!(
+                (HANGOVER_MS as usize..HANGOVER_MS as usize + 64).contains(&ms),
+                "{}",
+                ms
+            );
+        }
+    }
+
+    #[test]
+    fn floor_falls_when_the_background_quietens() {
+        // Speech at a level that would be lost in the earlier noise stands out
+        // once the background has quietened
+        let mut vad = vad();
+        let noise = corpus::white(0.2, 2.0, 3);
+        assert!(!vad.is_voice_segment(&noise));
+        let quiet = corpus::white(0.005, 1.0, 4);
+        assert!(!vad.is_voice_segment(&quiet));
+        assert!(vad.is_voice_segment(&corpus::speech(0.03, 0.5)));
+    }
+
+    #[test]
+    fn padding_stays_within_the_recording() {
+        assert_eq!(padded(&(100..200), 50, 1000), 50..250);
+        assert_eq!(padded(&(10..990), 50, 1000), 0..1000);
+    }
+
+    #[test]
+    fn rejects_silero_without_a_model() {
+        let config = VadConfig {
+            backend: VadBackend::Silero,
+            ..VadConfig::default()
+        };
+        assert!(config.validate().is_err());
     }
 }
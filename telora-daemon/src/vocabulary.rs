@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::path::Path;
+
+/// Product names, identifiers and other terms whisper should spell the way we do.
+///
+/// Read from a plain text file with one term per line; blank lines and lines
+/// starting with `#` are ignored. A missing file means an empty vocabulary.
+#[derive(Debug, Clone, Default)]
+pub struct Vocabulary {
+    pub path: String,
+    pub terms: Vec<String>,
+}
+
+impl Vocabulary {
+    pub fn load(path: &str) -> Result<Self> {
+        if path.is_empty() || !Path::new(path).exists() {
+            return Ok(Self {
+                path: path.to_string(),
+                terms: Vec::new(),
+            });
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read vocabulary {}", path))?;
+        let terms: Vec<String> = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+
+        info!("Loaded {} vocabulary terms from {}", terms.len(), path);
+        Ok(Self {
+            path: path.to_string(),
+            terms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let dir = std::env::temp_dir().join("telora-vocabulary");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("terms.txt");
+        std::fs::write(
+            &path,
+            "# Products\nTelora\n\n  whisper.cpp  \n# People\nNuria\n",
+        )
+        .unwrap();
+
+        let vocabulary = Vocabulary::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(vocabulary.terms, ["Telora", "whisper.cpp", "Nuria"]);
+    }
+
+    #[test]
+    fn missing_file_is_empty() {
+        let vocabulary = Vocabulary::load("/nowhere/terms.txt").unwrap();
+        assert!(vocabulary.terms.is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+
+/// What the client should do with a result.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    /// Type the text as is
+    Text { text: String },
+    /// Press key combinations in order, e.g. `["ctrl+z"]` or `["Return", "Return"]`
+    Keys { keys: Vec<String> },
+    /// Erase the text typed by the previous result
+    DeleteLast,
+}
+
+impl Default for Action {
+    fn default() -> Self {
+        Action::Text {
+            text: String::new(),
+        }
+    }
+}
+
+/// A phrase that triggers an action when it is the whole utterance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoiceCommand {
+    pub language: String,
+    pub phrase: String,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Settings for the `[voice_commands]` config table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoiceCommandConfig {
+    pub enabled: bool,
+    /// Include the built-in commands for Spanish and English
+    pub builtin: bool,
+    /// Extra commands; they take precedence over built-in ones with the same phrase
+    pub commands: Vec<VoiceCommand>,
+}
+
+impl Default for VoiceCommandConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            builtin: true,
+            commands: Vec::new(),
+        }
+    }
+}
+
+/// Recognises spoken commands in transcripts.
+///
+/// Only whole utterances match, so "deshacer" said on its own is a command while
+/// "hay que deshacer el cambio" is dictated normally.
+pub struct CommandMatcher {
+    enabled: bool,
+    commands: Vec<VoiceCommand>,
+}
+
+impl CommandMatcher {
+    pub fn new(config: &VoiceCommandConfig) -> Self {
+        let mut commands: Vec<VoiceCommand> = config
+            .commands
+            .iter()
+            .map(|c| VoiceCommand {
+                phrase: normalize(&c.phrase),
+                ..c.clone()
+            })
+            .collect();
+        if config.builtin {
+            commands.extend(builtin_commands());
+        }
+        Self {
+            enabled: config.enabled,
+            commands,
+        }
+    }
+
+    pub fn find(&self, text: &str, language: &str) -> Option<Action> {
+        if !self.enabled {
+            return None;
+        }
+        let spoken = normalize(text);
+        self.commands
+            .iter()
+            .find(|c| c.language == language && c.phrase == spoken)
+            .map(|c| c.action.clone())
+    }
+}
+
+/// Lowercases and drops the punctuation whisper adds, so "Deshacer." matches "deshacer".
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| !matches!(c, '.' | ',' | ';' | ':' | '!' | '?' | '¡' | '¿' | '"'))
+        .flat_map(char::to_lowercase)
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn builtin_commands() -> Vec<VoiceCommand> {
+    let keys = |combos: &[&str]| Action::Keys {
+        keys: combos.iter().map(|k| k.to_string()).collect(),
+    };
+    let table = [
+        ("es", "borrar eso", Action::DeleteLast),
+        ("es", "deshacer", keys(&["ctrl+z"])),
+        ("es", "rehacer", keys(&["ctrl+shift+z"])),
+        ("es", "seleccionar todo", keys(&["ctrl+a"])),
+        ("es", "nueva línea", keys(&["Return"])),
+        ("es", "nuevo párrafo", keys(&["Return", "Return"])),
+        ("es", "tabulador", keys(&["Tab"])),
+        ("en", "delete that", Action::DeleteLast),
+        ("en", "scratch that", Action::DeleteLast),
+        ("en", "undo", keys(&["ctrl+z"])),
+        ("en", "redo", keys(&["ctrl+shift+z"])),
+        ("en", "select all", keys(&["ctrl+a"])),
+        ("en", "new line", keys(&["Return"])),
+        ("en", "new paragraph", keys(&["Return", "Return"])),
+        ("en", "press tab", keys(&["Tab"])),
+    ];
+    table
+        .into_iter()
+        .map(|(language, phrase, action)| VoiceCommand {
+            language: language.to_string(),
+            phrase: phrase.to_string(),
+            action,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(commands: Vec<VoiceCommand>) -> CommandMatcher {
+        CommandMatcher::new(&VoiceCommandConfig {
+            enabled: true,
+            builtin: true,
+            commands,
+        })
+    }
+
+    #[test]
+    fn matches_whole_utterances_only() {
+        let m = matcher(Vec::new());
+        assert_eq!(m.find(" Borrar eso.", "es"), Some(Action::DeleteLast));
+        assert_eq!(
+            m.find("Select all!", "en"),
+            Some(Action::Keys {
+                keys: vec!["ctrl+a".to_string()]
+            })
+        );
+        assert_eq!(m.find("hay que deshacer el cambio", "es"), None);
+        // Tables are per language
+        assert_eq!(m.find("undo", "es"), None);
+    }
+
+    #[test]
+    fn custom_commands_take_precedence() {
+        let m = matcher(vec![VoiceCommand {
+            language: "es".to_string(),
+            phrase: "Deshacer".to_string(),
+            action: Action::Text {
+                text: "deshacer".to_string(),
+            },
+        }]);
+        assert_eq!(
+            m.find("deshacer", "es"),
+            Some(Action::Text {
+                text: "deshacer".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn disabled_matcher_never_matches() {
+        let m = CommandMatcher::new(&VoiceCommandConfig::default());
+        assert_eq!(m.find("deshacer", "es"), None);
+    }
+
+    #[test]
+    fn commands_deserialize_from_config() {
+        let config: VoiceCommandConfig = serde_json::from_str(
+            r#"{"enabled": true, "commands": [
+                {"language": "en", "phrase": "save it", "type": "keys", "keys": ["ctrl+s"]}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(config.builtin);
+        assert_eq!(
+            CommandMatcher::new(&config).find("Save it.", "en"),
+            Some(Action::Keys {
+                keys: vec!["ctrl+s".to_string()]
+            })
+        );
+    }
+}
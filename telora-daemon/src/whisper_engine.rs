@@ -0,0 +1,308 @@
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use std::ffi::c_void;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use whisper_rs::{
+    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperError,
+    WhisperState,
+};
+
+use crate::transcriber::{
+    AUTO_LANGUAGE, Capabilities, DecodeOptions, Segment, SpeechEngine, Token, Transcript,
+};
+
+/// whisper.cpp backend.
+#[derive(Default)]
+pub struct WhisperEngine {
+    ctx: Option<WhisperContext>,
+    use_gpu: bool,
+}
+
+impl SpeechEngine for WhisperEngine {
+    fn name(&self) -> &'static str {
+        "whisper"
+    }
+
+    fn load(&mut self, model_path: &str) -> Result<()> {
+        if !Path::new(model_path).exists() {
+            return Err(anyhow!("Model file not found: {}", model_path));
+        }
+
+        info!("Loading Whisper model from {}...", model_path);
+
+        // Load the model, on the GPU when built with CUDA support
+        self.use_gpu = cfg!(feature = "cuda");
+        let ctx = match init_context(model_path, self.use_gpu) {
+            Ok(ctx) => ctx,
+            Err(e) if self.use_gpu => {
+                warn!("GPU initialization failed ({}), retrying on CPU", e);
+                self.use_gpu = false;
+                init_context(model_path, false).context("Failed to load Whisper model")?
+            }
+            Err(e) => return Err(e).context("Failed to load Whisper model"),
+        };
+
+        info!(
+            "Whisper model loaded successfully ({} backend).",
+            self.backend()
+        );
+        self.ctx = Some(ctx);
+        Ok(())
+    }
+
+    fn transcribe(&mut self, audio_data: &[f32], options: &DecodeOptions) -> Result<Transcript> {
+        let ctx = self
+            .ctx
+            .as_ref()
+            .ok_or_else(|| anyhow!("No Whisper model loaded"))?;
+        let mut state = ctx
+            .create_state()
+            .context("Failed to create Whisper state")?;
+
+        let (language, language_probability) = if options.language == AUTO_LANGUAGE {
+            let (code, probability) =
+                detect_language(&mut state, audio_data, &options.language_candidates)?;
+            info!("Detected language: {} ({:.2})", code, probability);
+            (code, Some(probability))
+        } else {
+            (options.language.clone(), None)
+        };
+
+        // Configure parameters
+        let decoding = &options.decoding;
+        let strategy = if decoding.beam_size > 1 {
+            SamplingStrategy::BeamSearch {
+                beam_size: decoding.beam_size as i32,
+                // Disabled in whisper.cpp
+                patience: -1.0,
+            }
+        } else {
+            SamplingStrategy::Greedy {
+                best_of: decoding.best_of as i32,
+            }
+        };
+        let mut params = FullParams::new(strategy);
+        params.set_language(Some(&language));
+        params.set_n_threads(match decoding.threads {
+            0 => inference_threads() as i32,
+            n => n as i32,
+        });
+        params.set_temperature(decoding.temperature);
+        params.set_no_speech_thold(decoding.no_speech_threshold);
+        params.set_suppress_blank(decoding.suppress_blank);
+        params.set_suppress_non_speech_tokens(decoding.suppress_non_speech_tokens);
+        let prompt = options.prompt();
+        if !prompt.is_empty() {
+            params.set_initial_prompt(&prompt);
+        }
+        params.set_print_special(false);
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_timestamps(false);
+
+        // Checked by whisper between decoding steps, so CANCEL stops long recordings early.
+        // The closure-based setter of whisper-rs 0.13 reads its user data back with the
+        // wrong type, so the flag is handed over directly.
+        let cancel = options.cancel.clone();
+        // SAFETY: `cancel` keeps the flag alive until `full` has returned
+        unsafe {
+            params.set_abort_callback(Some(abort_requested));
+            params.set_abort_callback_user_data(cancel.as_ptr().cast_mut().cast::<c_void>());
+        }
+
+        // Run the transcription
+        let outcome = state.full(params, audio_data);
+        drop(cancel);
+        if options.cancel.is_cancelled() {
+            return Err(anyhow!("Transcription cancelled"));
+        }
+        outcome.context("Failed to run full transcription")?;
+
+        // Fetch the results
+        let num_segments = state
+            .full_n_segments()
+            .context("Failed to get number of segments")?;
+        let mut segments = Vec::with_capacity(num_segments as usize);
+
+        for i in 0..num_segments {
+            let text = state
+                .full_get_segment_text(i)
+                .context("Failed to get segment text")?;
+            // Whisper reports timestamps in centiseconds
+            let t0 = state
+                .full_get_segment_t0(i)
+                .context("Failed to get segment start")?;
+            let t1 = state
+                .full_get_segment_t1(i)
+                .context("Failed to get segment end")?;
+
+            let num_tokens = state
+                .full_n_tokens(i)
+                .context("Failed to get number of tokens")?;
+            let mut tokens = Vec::with_capacity(num_tokens as usize);
+            for j in 0..num_tokens {
+                let token_text = state
+                    .full_get_token_text_lossy(i, j)
+                    .context("Failed to get token text")?;
+                // Skip control tokens such as [_BEG_] or <|endoftext|>
+                if token_text.starts_with("[_") || token_text.starts_with("<|") {
+                    continue;
+                }
+                let probability = state
+                    .full_get_token_prob(i, j)
+                    .context("Failed to get token probability")?;
+                tokens.push(Token {
+                    text: token_text,
+                    probability,
+                });
+            }
+
+            let confidence = if tokens.is_empty() {
+                0.0
+            } else {
+                tokens.iter().map(|t| t.probability).sum::<f32>() / tokens.len() as f32
+            };
+
+            segments.push(Segment {
+                start_ms: t0.max(0) as u64 * 10,
+                end_ms: t1.max(0) as u64 * 10,
+                text,
+                confidence,
+                tokens,
+            });
+        }
+
+        Ok(Transcript {
+            language,
+            language_probability,
+            segments,
+        })
+    }
+
+    fn backend(&self) -> &'static str {
+        if self.use_gpu { "cuda" } else { "cpu" }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            language_detection: true,
+            token_probabilities: true,
+        }
+    }
+
+    fn unload(&mut self) {
+        if self.ctx.take().is_some() {
+            info!("Whisper model unloaded.");
+        }
+    }
+}
+
+/// Abort callback for whisper; `data` is the flag of a `CancelToken`.
+unsafe extern "C" fn abort_requested(data: *mut c_void) -> bool {
+    // SAFETY: set from `CancelToken::as_ptr` by a caller that keeps the token alive
+    unsafe { (*data.cast::<AtomicBool>()).load(Ordering::Relaxed) }
+}
+
+fn init_context(model_path: &str, use_gpu: bool) -> Result<WhisperContext, WhisperError> {
+    let mut params = WhisperContextParameters::default();
+    params.use_gpu(use_gpu);
+    WhisperContext::new_with_params(model_path, params)
+}
+
+fn inference_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(8)
+}
+
+/// Runs whisper's language identification, optionally restricted to `candidates`.
+fn detect_language(
+    state: &mut WhisperState,
+    audio_data: &[f32],
+    candidates: &[String],
+) -> Result<(String, f32)> {
+    let threads = inference_threads();
+    state
+        .pcm_to_mel(audio_data, threads)
+        .context("Failed to compute spectrogram")?;
+    let (detected, probs) = state
+        .lang_detect(0, threads)
+        .context("Failed to detect language")?;
+
+    let best = candidates
+        .iter()
+        .filter_map(|code| match whisper_rs::get_lang_id(code) {
+            Some(id) => Some((code.clone(), probs[id as usize])),
+            None => {
+                warn!("Ignoring unknown candidate language: {}", code);
+                None
+            }
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+
+    if let Some(best) = best {
+        return Ok(best);
+    }
+
+    let code = whisper_rs::get_lang_str(detected)
+        .ok_or_else(|| anyhow!("Unknown language id: {}", detected))?;
+    Ok((code.to_string(), probs[detected as usize]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcriber::CancelToken;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn abort_callback_reads_the_cancel_flag() {
+        let cancel = CancelToken::default();
+        let data = cancel.as_ptr().cast_mut().cast::<c_void>();
+        assert!(!unsafe { abort_requested(data) });
+        cancel.clone().cancel();
+        assert!(unsafe { abort_requested(data) });
+    }
+
+    /// Needs a real model: `TELORA_TEST_MODEL=ggml-tiny.bin cargo test -- --ignored`
+    #[test]
+    #[ignore = "needs a whisper model in TELORA_TEST_MODEL"]
+    fn cancel_stops_a_running_decode() {
+        let model = std::env::var("TELORA_TEST_MODEL").unwrap();
+        let mut engine = WhisperEngine::default();
+        engine.load(&model).unwrap();
+
+        let cancel = CancelToken::default();
+        let options = DecodeOptions {
+            cancel: cancel.clone(),
+            ..DecodeOptions::for_test("en")
+        };
+        // Thirty seconds of a tone keeps whisper busy well past the cancel
+        let audio: Vec<f32> = (0..16000 * 30)
+            .map(|i| 0.1 * (i as f32 * 0.07).sin())
+            .collect();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            cancel.cancel();
+        });
+
+        let started = Instant::now();
+        let err = engine.transcribe(&audio, &options).unwrap_err();
+        let cancelled = started.elapsed();
+        canceller.join().unwrap();
+        assert_eq!(err.to_string(), "Transcription cancelled");
+        // The decode stopped rather than running to the end
+        let uncancelled = {
+            let options = DecodeOptions {
+                cancel: CancelToken::default(),
+                ..options
+            };
+            let started = Instant::now();
+            engine.transcribe(&audio, &options).unwrap();
+            started.elapsed()
+        };
+        assert!(cancelled < uncancelled);
+    }
+}
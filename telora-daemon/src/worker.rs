@@ -0,0 +1,345 @@
+use anyhow::{Result, anyhow};
+use log::{error, info};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use tokio::sync::mpsc as tokio_mpsc;
+
+use crate::events::{Event, EventSender, emit};
+use crate::model_pool::{ModelPool, ModelPoolConfig};
+use crate::partial::PartialTranscriber;
+use crate::transcriber::{Capabilities, DecodeOptions, Transcript};
+
+/// Work handed to the inference thread. Jobs run one at a time, in order.
+pub enum Job {
+    /// Make sure a model is resident before a recording needs it; answered with
+    /// `Output::Loaded`
+    Load {
+        recording: u64,
+        model_path: String,
+    },
+    /// Apply new pool settings and load the configured model; answered with
+    /// `Output::Configured`. The previous settings are restored if the model fails to load.
+    Configure {
+        engine: String,
+        pool: ModelPoolConfig,
+        /// Load `pool.preload` again, because the list changed
+        preload: bool,
+        model_path: String,
+        /// Emit `model_reloaded` once the model is ready
+        announce: bool,
+    },
+    Transcribe(Box<TranscribeJob>),
+}
+
+pub struct TranscribeJob {
+    /// Recording this audio belongs to; results of older recordings are discarded
+    pub recording: u64,
+    pub model_path: String,
+    pub audio: Vec<f32>,
+    pub options: DecodeOptions,
+    pub work: Work,
+    pub submitted: Instant,
+}
+
+pub enum Work {
+    /// Refresh the running transcript of a streaming recording
+    Partial(PartialTranscriber),
+    /// Decode a stopped recording, finishing its streaming state if there is one
+    Final(Option<PartialTranscriber>),
+    /// Decode one dictated utterance
+    Utterance,
+}
+
+pub struct JobResult {
+    /// Recording the job belongs to; 0 for `Configure`
+    pub recording: u64,
+    pub samples: usize,
+    /// From submission to completion, including time spent queued
+    pub latency_ms: u64,
+    pub output: Output,
+}
+
+pub enum Output {
+    Loaded(Result<()>),
+    Configured(Result<()>),
+    /// The partial state comes back so the next update can continue from it
+    Partial {
+        partial: PartialTranscriber,
+        text: Result<String>,
+    },
+    Final(Result<Transcript>),
+    Utterance(Result<Transcript>),
+}
+
+/// What `status` reports about the models, readable while a job runs.
+#[derive(Debug, Clone, Default)]
+pub struct PoolStatus {
+    pub engine: String,
+    pub backend: String,
+    pub capabilities: Capabilities,
+    pub loaded_models: Vec<String>,
+}
+
+impl PoolStatus {
+    fn of(pool: &ModelPool) -> Self {
+        let current = pool.current();
+        Self {
+            engine: current
+                .map(|t| t.engine_name().to_string())
+                .unwrap_or_default(),
+            backend: current.map(|t| t.backend().to_string()).unwrap_or_default(),
+            capabilities: current.map(|t| t.capabilities()).unwrap_or_default(),
+            loaded_models: pool.loaded(),
+        }
+    }
+}
+
+/// Runs inference on a dedicated thread so the main loop keeps reading audio and
+/// commands while whisper works.
+pub struct Worker {
+    jobs: mpsc::Sender<Job>,
+    status: Arc<Mutex<PoolStatus>>,
+}
+
+impl Worker {
+    pub fn spawn(
+        pool: ModelPool,
+        events: EventSender,
+    ) -> Result<(Self, tokio_mpsc::UnboundedReceiver<JobResult>)> {
+        let (jobs_tx, jobs_rx) = mpsc::channel();
+        let (results_tx, results_rx) = tokio_mpsc::unbounded_channel();
+        let status = Arc::new(Mutex::new(PoolStatus::of(&pool)));
+
+        let shared = Arc::clone(&status);
+        thread::Builder::new()
+            .name("transcriber".to_string())
+            .spawn(move || run(pool, jobs_rx, results_tx, shared, events))?;
+
+        Ok((
+            Self {
+                jobs: jobs_tx,
+                status,
+            },
+            results_rx,
+        ))
+    }
+
+    pub fn submit(&self, job: Job) {
+        if self.jobs.send(job).is_err() {
+            error!("Transcription worker has stopped");
+        }
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        self.status.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+}
+
+fn run(
+    mut pool: ModelPool,
+    jobs: mpsc::Receiver<Job>,
+    results: tokio_mpsc::UnboundedSender<JobResult>,
+    status: Arc<Mutex<PoolStatus>>,
+    events: EventSender,
+) {
+    while let Ok(job) = jobs.recv() {
+        match job {
+            Job::Load {
+                recording,
+                model_path,
+            } => {
+                let started = Instant::now();
+                let outcome = pool.get(&model_path).map(|_| ());
+                if let Err(e) = &outcome {
+                    error!("Failed to load model: {:#}", e);
+                }
+                let result = JobResult {
+                    recording,
+                    samples: 0,
+                    latency_ms: started.elapsed().as_millis() as u64,
+                    output: Output::Loaded(outcome),
+                };
+                publish(&status, &pool);
+                if results.send(result).is_err() {
+                    break;
+                }
+            }
+            Job::Configure {
+                engine,
+                pool: config,
+                preload,
+                model_path,
+                announce,
+            } => {
+                let started = Instant::now();
+                let (previous_engine, previous_config) = pool.settings();
+                pool.configure(&engine, &config);
+                if preload {
+                    pool.preload(&config.preload);
+                }
+                let outcome = match pool.get(&model_path) {
+                    Ok(_) => {
+                        if announce {
+                            info!("Switched to model {}", model_path);
+                            emit(&events, Event::ModelReloaded { model_path });
+                        }
+                        Ok(())
+                    }
+                    Err(e) => {
+                        error!("Failed to load model: {:#}", e);
+                        emit(
+                            &events,
+                            Event::Error {
+                                message: format!("Failed to load model: {:#}", e),
+                            },
+                        );
+                        // The daemon keeps its current config, so the pool does too
+                        pool.configure(&previous_engine, &previous_config);
+                        Err(e)
+                    }
+                };
+                let result = JobResult {
+                    recording: 0,
+                    samples: 0,
+                    latency_ms: started.elapsed().as_millis() as u64,
+                    output: Output::Configured(outcome),
+                };
+                publish(&status, &pool);
+                if results.send(result).is_err() {
+                    break;
+                }
+            }
+            Job::Transcribe(job) => {
+                let recording = job.recording;
+                let samples = job.audio.len();
+                let submitted = job.submitted;
+                let output = transcribe(&mut pool, *job);
+                let result = JobResult {
+                    recording,
+                    samples,
+                    latency_ms: submitted.elapsed().as_millis() as u64,
+                    output,
+                };
+                // Publish the status first, so a result never arrives ahead of it
+                publish(&status, &pool);
+                if results.send(result).is_err() {
+                    break;
+                }
+            }
+        }
+
+        publish(&status, &pool);
+    }
+}
+
+fn publish(status: &Mutex<PoolStatus>, pool: &ModelPool) {
+    if let Ok(mut s) = status.lock() {
+        *s = PoolStatus::of(pool);
+    }
+}
+
+fn transcribe(pool: &mut ModelPool, job: TranscribeJob) -> Output {
+    let TranscribeJob {
+        model_path,
+        audio,
+        options,
+        work,
+        ..
+    } = job;
+
+    // Queued jobs of a cancelled recording are skipped without touching the model
+    let cancelled = || anyhow!("Transcription cancelled");
+
+    match work {
+        Work::Partial(mut partial) => {
+            let text = if options.cancel.is_cancelled() {
+                Err(cancelled())
+            } else {
+                pool.get(&model_path)
+                    .and_then(|t| partial.update(t, &audio, &options))
+            };
+            Output::Partial { partial, text }
+        }
+        Work::Final(partial) => {
+            let transcript = if options.cancel.is_cancelled() {
+                Err(cancelled())
+            } else if audio.is_empty() {
+                Ok(Transcript::default())
+            } else {
+                pool.get(&model_path).and_then(|t| match partial {
+                    Some(mut partial) => partial.finish(t, &audio, &options),
+                    None => t.transcribe(&audio, &options),
+                })
+            };
+            Output::Final(transcript)
+        }
+        Work::Utterance => {
+            let transcript = if options.cancel.is_cancelled() {
+                Err(cancelled())
+            } else {
+                pool.get(&model_path)
+                    .and_then(|t| t.transcribe(&audio, &options))
+            };
+            Output::Utterance(transcript)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events;
+    use crate::transcriber::CancelToken;
+
+    fn spawn() -> (Worker, tokio_mpsc::UnboundedReceiver<JobResult>) {
+        let pool = ModelPool::new("mock", &ModelPoolConfig::default());
+        Worker::spawn(pool, events::channel()).unwrap()
+    }
+
+    fn job(recording: u64, cancel: &CancelToken) -> Job {
+        Job::Transcribe(Box::new(TranscribeJob {
+            recording,
+            model_path: "mock.bin".to_string(),
+            // One second of loud audio
+            audio: (0..16000)
+                .map(|i| if i % 2 == 0 { 0.3 } else { -0.3 })
+                .collect(),
+            options: DecodeOptions {
+                cancel: cancel.clone(),
+                ..DecodeOptions::for_test("es")
+            },
+            work: Work::Final(None),
+            submitted: Instant::now(),
+        }))
+    }
+
+    #[test]
+    fn transcribes_on_the_worker_thread() {
+        let (worker, mut results) = spawn();
+        worker.submit(job(7, &CancelToken::default()));
+
+        let done = results.blocking_recv().unwrap();
+        assert_eq!(done.recording, 7);
+        assert_eq!(done.samples, 16000);
+        match done.output {
+            Output::Final(Ok(transcript)) => assert_eq!(transcript.text(), "1000 ms of speech"),
+            _ => panic!("expected a final transcript"),
+        }
+        assert_eq!(worker.status().loaded_models, vec!["mock.bin".to_string()]);
+    }
+
+    #[test]
+    fn cancelled_jobs_are_skipped() {
+        let (worker, mut results) = spawn();
+        let cancel = CancelToken::default();
+        cancel.cancel();
+        worker.submit(job(1, &cancel));
+
+        match results.blocking_recv().unwrap().output {
+            Output::Final(Err(e)) => assert_eq!(e.to_string(), "Transcription cancelled"),
+            _ => panic!("expected the job to be cancelled"),
+        }
+    }
+}
@@ -1,30 +1,178 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use serde_json::{Value, json};
 use std::path::Path;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
 
 pub const DAEMON_SOCKET: &str = "/tmp/telora-sock";
 pub const CONTROL_SOCKET: &str = "/tmp/telora-control.sock";
 
+/// JSON-RPC protocol version spoken with the daemon.
+const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<RpcError>,
+}
+
+/// Result of a recording as returned by the daemon's `stop` method.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Transcription {
+    pub text: String,
+    pub duration_ms: u64,
+    pub latency_ms: u64,
+    pub language: String,
+    /// Absent when talking to an older daemon; `text` is typed then
+    pub action: Option<Action>,
+}
+
+impl Transcription {
+    /// What to do with this result on the desktop.
+    pub fn action(&self) -> Action {
+        self.action.clone().unwrap_or_else(|| Action::Text {
+            text: self.text.clone(),
+        })
+    }
+}
+
+/// What the daemon wants done with a result.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Text { text: String },
+    Keys { keys: Vec<String> },
+    DeleteLast,
+}
+
+/// Events pushed by the daemon after `subscribe`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DaemonEvent {
+    AutoStop {
+        reason: String,
+    },
+    Partial {
+        text: String,
+    },
+    /// `output_mode` is "type" or "copy"
+    ProfileChanged {
+        profile: String,
+        output_mode: String,
+    },
+    Final {
+        dictation: bool,
+        result: Transcription,
+    },
+    Error {
+        message: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// A subscribed connection that yields daemon events.
+pub struct EventStream {
+    stream: BufReader<UnixStream>,
+}
+
+impl EventStream {
+    pub async fn next(&mut self) -> Result<DaemonEvent> {
+        loop {
+            let mut buf = String::new();
+            if self.stream.read_line(&mut buf).await? == 0 {
+                return Err(anyhow!("Daemon closed the connection"));
+            }
+
+            let message: Value =
+                serde_json::from_str(&buf).context("Failed to parse daemon message")?;
+            if message.get("method").and_then(Value::as_str) == Some("event")
+                && let Some(params) = message.get("params")
+            {
+                return Ok(serde_json::from_value(params.clone())?);
+            }
+        }
+    }
+}
+
 pub struct SocketClient;
 
 impl SocketClient {
-    pub async fn send_command(cmd: &str) -> Result<String> {
-        let mut stream = UnixStream::connect(DAEMON_SOCKET)
+    /// Opens a connection, performs the handshake and issues a single request.
+    pub async fn call(method: &str, params: Value) -> Result<Value> {
+        let mut stream = Self::connect().await?;
+        Self::request(&mut stream, 1, method, params).await
+    }
+
+    /// Opens a connection that streams daemon events until it is dropped.
+    pub async fn subscribe() -> Result<EventStream> {
+        let mut stream = Self::connect().await?;
+        Self::request(&mut stream, 1, "subscribe", Value::Null).await?;
+        Ok(EventStream { stream })
+    }
+
+    async fn connect() -> Result<BufReader<UnixStream>> {
+        let stream = UnixStream::connect(DAEMON_SOCKET)
             .await
             .context("Failed to connect to daemon")?;
+        let mut stream = BufReader::new(stream);
+
+        Self::request(
+            &mut stream,
+            0,
+            "hello",
+            json!({ "protocol_version": PROTOCOL_VERSION }),
+        )
+        .await?;
+        Ok(stream)
+    }
+
+    async fn request(
+        stream: &mut BufReader<UnixStream>,
+        id: u64,
+        method: &str,
+        params: Value,
+    ) -> Result<Value> {
+        let mut line = serde_json::to_string(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }))?;
+        line.push('\n');
         stream
-            .write_all(cmd.as_bytes())
+            .get_mut()
+            .write_all(line.as_bytes())
             .await
             .context("Failed to send command")?;
 
         // Wait for response
-        let mut buf = Vec::new();
-        stream
-            .read_to_end(&mut buf)
+        let mut buf = String::new();
+        if stream
+            .read_line(&mut buf)
             .await
-            .context("Failed to read response from daemon")?;
-        Ok(String::from_utf8_lossy(&buf).to_string())
+            .context("Failed to read response from daemon")?
+            == 0
+        {
+            return Err(anyhow!("Daemon closed the connection"));
+        }
+
+        let response: RpcResponse =
+            serde_json::from_str(&buf).context("Failed to parse daemon response")?;
+        if let Some(e) = response.error {
+            return Err(anyhow!("Daemon error {}: {}", e.code, e.message));
+        }
+        Ok(response.result.unwrap_or(Value::Null))
     }
 
     pub async fn send_control_command(cmd: &str) -> Result<()> {
@@ -55,8 +203,9 @@ impl ControlServer {
 
     pub async fn next_command(&self) -> Result<String> {
         let (mut stream, _) = self.listener.accept().await?;
-        let mut buf = [0; 1024];
-        let n = stream.read(&mut buf).await?;
-        Ok(String::from_utf8_lossy(&buf[..n]).trim().to_string())
+        // Senders close the connection after writing
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        Ok(String::from_utf8_lossy(&buf).trim().to_string())
     }
 }
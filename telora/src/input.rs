@@ -1,20 +1,84 @@
 use log::{error, info};
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::connection::Action;
+
+// Characters typed by the last `type_text`, erased by a "delete that" command
+static LAST_TYPED: AtomicUsize = AtomicUsize::new(0);
+
+pub fn perform(action: &Action) {
+    match action {
+        Action::Text { text } => type_text(text),
+        Action::Keys { keys } => send_keys(keys),
+        Action::DeleteLast => delete_last(),
+    }
+}
 
 pub fn type_text(text: &str) {
-    if text.trim().is_empty() {
+    // Whitespace alone is still worth typing: it may be a dictated line break
+    if text.is_empty() {
         return;
     }
 
     // wtype
     let res = Command::new("wtype").arg(text).output();
 
-    match res {
-        Ok(_) => {}
+    let typed = match res {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            error!(
+                "wtype exited with {}: {}. Trying clipboard fallback.",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            false
+        }
         Err(e) => {
             error!("wtype failed: {}. Trying clipboard fallback.", e);
-            copy_text(text);
+            false
         }
+    };
+
+    if typed {
+        LAST_TYPED.store(text.chars().count(), Ordering::Relaxed);
+    } else {
+        // Nothing was typed, so "delete that" has nothing to erase
+        LAST_TYPED.store(0, Ordering::Relaxed);
+        copy_text(text);
+    }
+}
+
+/// Presses key combinations such as "ctrl+z" or "Return", one after another.
+pub fn send_keys(combos: &[String]) {
+    let mut args: Vec<&str> = Vec::new();
+    for combo in combos {
+        let mut parts: Vec<&str> = combo.split('+').collect();
+        let Some(key) = parts.pop() else { continue };
+        for modifier in &parts {
+            args.extend(["-M", modifier]);
+        }
+        args.extend(["-k", key]);
+        for modifier in parts.iter().rev() {
+            args.extend(["-m", modifier]);
+        }
+    }
+    if args.is_empty() {
+        return;
+    }
+
+    if let Err(e) = Command::new("wtype").args(&args).output() {
+        error!("wtype failed to send keys {:?}: {}", combos, e);
+    }
+    // Keys may have moved the cursor; the last text can no longer be erased reliably
+    LAST_TYPED.store(0, Ordering::Relaxed);
+}
+
+/// Erases the text typed by the previous result with backspaces.
+pub fn delete_last() {
+    let count = LAST_TYPED.swap(0, Ordering::Relaxed);
+    if count > 0 {
+        send_keys(&vec!["BackSpace".to_string(); count]);
     }
 }
 
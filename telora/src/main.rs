@@ -1,5 +1,5 @@
 use async_channel::Sender;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use gtk4::prelude::*;
 use gtk4::{Application, glib};
 use std::thread;
@@ -7,12 +7,13 @@ use tokio::runtime::Runtime;
 use tokio::sync::mpsc;
 
 use log::info;
+use serde_json::{Value, json};
 
 mod connection;
 mod input;
 mod ui;
 
-use connection::{ControlServer, SocketClient};
+use connection::{Action, ControlServer, DaemonEvent, SocketClient, Transcription};
 use ui::Osd;
 
 #[derive(Parser)]
@@ -24,19 +25,55 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Toggle recording and type or copy the result, as the active profile says
+    Toggle,
     /// Toggle recording and type the result
     ToggleType,
     /// Toggle recording and copy the result to clipboard
     ToggleCopy,
     /// Cancel current recording
     Cancel,
+    /// Start hands-free dictation: each utterance is typed as soon as you pause
+    DictateStart,
+    /// Stop hands-free dictation
+    DictateStop,
+    /// Export the most recent recording as subtitles or JSON
+    Export {
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Srt)]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Switch the daemon to a named profile from its config ("default" for none)
+    Profile { name: String },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    Srt,
+    Vtt,
+    Json,
+}
+
+impl ExportFormat {
+    fn as_str(self) -> &'static str {
+        match self {
+            ExportFormat::Srt => "srt",
+            ExportFormat::Vtt => "vtt",
+            ExportFormat::Json => "json",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 enum AppAction {
-    ToggleRecording(String, bool), // mode, is_auto_stop
+    ToggleRecording(String, Option<String>), // mode, auto-stop reason
     CancelRecording,
+    SetDictation(bool),
     OsdUpdate(String, String), // Text, Color
+    PartialText(String),
+    SetProfile(String, String), // Name, output mode
     OsdHide,
 }
 
@@ -48,6 +85,8 @@ enum DaemonCommand {
         response_tx: Sender<AppAction>,
     },
     Cancel,
+    DictateStart,
+    DictateStop,
 }
 
 fn main() {
@@ -55,13 +94,36 @@ fn main() {
 
     let cli = Cli::parse();
 
-    if let Some(command) = cli.command {
-        let cmd_str = match command {
-            Commands::ToggleType => "TOGGLE_TYPE",
-            Commands::ToggleCopy => "TOGGLE_COPY",
-            Commands::Cancel => "CANCEL",
-        };
+    let control = match cli.command {
+        Some(Commands::Export { format, output }) => {
+            let rt = Runtime::new().expect("Failed to create Tokio runtime");
+            if let Err(e) = rt.block_on(run_export(format, output.as_deref())) {
+                log::error!("Failed to export: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Profile { name }) => {
+            let rt = Runtime::new().expect("Failed to create Tokio runtime");
+            match rt.block_on(SocketClient::call("profile", json!({ "name": name }))) {
+                Ok(_) => info!("Switched to profile '{}'", name),
+                Err(e) => {
+                    log::error!("Failed to switch profile: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Commands::Toggle) => Some("TOGGLE"),
+        Some(Commands::ToggleType) => Some("TOGGLE_TYPE"),
+        Some(Commands::ToggleCopy) => Some("TOGGLE_COPY"),
+        Some(Commands::Cancel) => Some("CANCEL"),
+        Some(Commands::DictateStart) => Some("DICTATE_START"),
+        Some(Commands::DictateStop) => Some("DICTATE_STOP"),
+        None => None,
+    };
 
+    if let Some(cmd_str) = control {
         let rt = Runtime::new().expect("Failed to create Tokio runtime");
         rt.block_on(async {
             match SocketClient::send_control_command(cmd_str).await {
@@ -99,6 +161,7 @@ fn main() {
                             log::error!("Control server failed: {}", e);
                         }
                     }
+                    _ = run_event_listener(tx_clone.clone()) => {}
                     _ = handle_daemon_commands(daemon_rx, tx_clone) => {}
                 }
             });
@@ -111,21 +174,29 @@ fn main() {
         // GTK Main Loop Context
         glib::MainContext::default().spawn_local(async move {
             let mut recording = false;
+            let mut dictating = false;
             let mut current_mode = String::new();
+            // Mode used by plain `toggle`, set by the daemon's active profile
+            let mut default_mode = "TYPE".to_string();
+            let mut active_profile: Option<String> = None;
 
             while let Ok(action) = rx.recv().await {
                 match action {
-                    AppAction::ToggleRecording(mode, is_auto_stop) => {
+                    AppAction::ToggleRecording(mode, auto_stop) => {
                         if !recording {
                             // START
                             recording = true;
-                            current_mode = mode;
+                            current_mode = if mode == "DEFAULT" {
+                                default_mode.clone()
+                            } else {
+                                mode
+                            };
                             osd_clone.show("● GRABANDO", "red");
                             let _ = daemon_tx.send(DaemonCommand::Start);
                         } else {
                             // STOP
                             recording = false;
-                            if is_auto_stop {
+                            if auto_stop.as_deref() == Some("max_duration") {
                                 osd_clone.show("⏳ LÍMITE ALCANZADO", "orange");
                             } else {
                                 osd_clone.show("Procesando...", "orange");
@@ -149,11 +220,46 @@ fn main() {
                             });
                         }
                     }
+                    AppAction::SetDictation(enable) => {
+                        if enable && !recording && !dictating {
+                            dictating = true;
+                            osd_clone.show("● DICTADO", "blue");
+                            let _ = daemon_tx.send(DaemonCommand::DictateStart);
+                        } else if !enable && dictating {
+                            dictating = false;
+                            osd_clone.hide();
+                            let _ = daemon_tx.send(DaemonCommand::DictateStop);
+                        }
+                    }
                     AppAction::OsdUpdate(text, color) => {
                         if !recording {
                             osd_clone.show(&text, &color);
                         }
                     }
+                    AppAction::PartialText(text) => {
+                        if recording {
+                            osd_clone.show_partial(&text);
+                        }
+                    }
+                    AppAction::SetProfile(name, output_mode) => {
+                        default_mode = if output_mode == "copy" {
+                            "COPY".to_string()
+                        } else {
+                            "TYPE".to_string()
+                        };
+                        osd_clone.set_profile(&name);
+                        // Announce switches, not the profile found when connecting
+                        let switched = active_profile.as_ref().is_some_and(|p| *p != name);
+                        active_profile = Some(name.clone());
+                        if switched && !recording && !dictating {
+                            osd_clone.show(&format!("Perfil: {}", name), "purple");
+                            let tx_inner = tx_back.clone();
+                            glib::timeout_add_seconds_local(1, move || {
+                                let _ = tx_inner.send_blocking(AppAction::OsdHide);
+                                glib::ControlFlow::Break
+                            });
+                        }
+                    }
                     AppAction::OsdHide => {
                         if !recording {
                             osd_clone.hide();
@@ -167,6 +273,21 @@ fn main() {
     app.run();
 }
 
+/// Asks the daemon to render its last transcript; talks to the daemon directly, not the GUI.
+async fn run_export(format: ExportFormat, output: Option<&std::path::Path>) -> anyhow::Result<()> {
+    let result = SocketClient::call("export", json!({ "format": format.as_str() })).await?;
+    let content = result
+        .get("content")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow::anyhow!("Malformed export response"))?;
+
+    match output {
+        Some(path) => std::fs::write(path, content)?,
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
 async fn handle_daemon_commands(
     mut rx: mpsc::UnboundedReceiver<DaemonCommand>,
     _tx: Sender<AppAction>,
@@ -174,17 +295,27 @@ async fn handle_daemon_commands(
     while let Some(cmd) = rx.recv().await {
         match cmd {
             DaemonCommand::Start => {
-                let _ = SocketClient::send_command("START").await;
+                let _ = SocketClient::call("start", Value::Null).await;
             }
             DaemonCommand::Stop { mode, response_tx } => {
                 // The STOP command now returns the transcription result directly
-                match SocketClient::send_command("STOP").await {
-                    Ok(text) if !text.trim().is_empty() && !text.starts_with("ERROR:") => {
+                let result = SocketClient::call("stop", Value::Null)
+                    .await
+                    .and_then(|v| Ok(serde_json::from_value::<Transcription>(v)?));
+                match result {
+                    Ok(transcription) if has_content(&transcription) => {
+                        info!(
+                            "Transcribed {} ms of audio in {} ms ({})",
+                            transcription.duration_ms,
+                            transcription.latency_ms,
+                            transcription.language
+                        );
                         let is_auto = mode == "AUTO";
                         if mode == "TYPE" || is_auto {
-                            input::type_text(&text);
+                            input::perform(&transcription.action());
                         } else {
-                            input::copy_text(&text);
+                            // Voice commands have nothing to copy
+                            input::copy_text(&transcription.text);
                         }
 
                         if is_auto {
@@ -208,10 +339,6 @@ async fn handle_daemon_commands(
 
                         let _ = response_tx.send(AppAction::OsdHide).await;
                     }
-                    Ok(text) if text.starts_with("ERROR:") => {
-                        log::error!("Daemon error: {}", text);
-                        let _ = response_tx.send(AppAction::OsdHide).await;
-                    }
                     Ok(_) => {
                         // Empty result
                         let _ = response_tx.send(AppAction::OsdHide).await;
@@ -223,7 +350,93 @@ async fn handle_daemon_commands(
                 }
             }
             DaemonCommand::Cancel => {
-                let _ = SocketClient::send_command("CANCEL").await;
+                let _ = SocketClient::call("cancel", Value::Null).await;
+            }
+            DaemonCommand::DictateStart => {
+                let _ = SocketClient::call("dictate_start", Value::Null).await;
+            }
+            DaemonCommand::DictateStop => {
+                let _ = SocketClient::call("dictate_stop", Value::Null).await;
+            }
+        }
+    }
+}
+
+fn has_content(transcription: &Transcription) -> bool {
+    match transcription.action() {
+        Action::Text { text } => !text.trim().is_empty(),
+        _ => true,
+    }
+}
+
+/// Follows the daemon's event stream, reconnecting whenever the daemon restarts.
+async fn run_event_listener(tx: Sender<AppAction>) {
+    loop {
+        let mut events = match SocketClient::subscribe().await {
+            Ok(events) => events,
+            Err(e) => {
+                log::debug!("Event subscription unavailable: {}", e);
+                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                continue;
+            }
+        };
+        info!("Subscribed to daemon events");
+
+        // Events only report profile switches; start from the daemon's current one
+        if let Ok(status) = SocketClient::call("status", Value::Null).await {
+            let field = |key: &str| {
+                status
+                    .get(key)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            let _ = tx
+                .send(AppAction::SetProfile(
+                    field("profile"),
+                    field("output_mode"),
+                ))
+                .await;
+        }
+
+        loop {
+            match events.next().await {
+                Ok(DaemonEvent::AutoStop { reason }) => {
+                    info!("Daemon auto-stop: {}", reason);
+                    let _ = tx
+                        .send(AppAction::ToggleRecording("AUTO".to_string(), Some(reason)))
+                        .await;
+                }
+                Ok(DaemonEvent::Partial { text }) => {
+                    let _ = tx.send(AppAction::PartialText(text)).await;
+                }
+                Ok(DaemonEvent::ProfileChanged {
+                    profile,
+                    output_mode,
+                }) => {
+                    info!("Daemon profile: {} ({})", profile, output_mode);
+                    let _ = tx.send(AppAction::SetProfile(profile, output_mode)).await;
+                }
+                Ok(DaemonEvent::Final {
+                    dictation: true,
+                    result,
+                }) => {
+                    match result.action() {
+                        // The daemon may already end results with a space (`trailing_space`)
+                        Action::Text { text } if !text.ends_with(char::is_whitespace) => {
+                            input::type_text(&format!("{} ", text));
+                        }
+                        action => input::perform(&action),
+                    }
+                }
+                Ok(DaemonEvent::Error { message }) => {
+                    log::warn!("Daemon error: {}", message);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log::warn!("Lost daemon event stream: {}", e);
+                    break;
+                }
             }
         }
     }
@@ -238,23 +451,29 @@ async fn run_control_server(tx: Sender<AppAction>) -> anyhow::Result<()> {
             Ok(cmd) => {
                 info!("Control command: {}", cmd);
                 match cmd.as_str() {
+                    "TOGGLE" => {
+                        let _ = tx
+                            .send(AppAction::ToggleRecording("DEFAULT".to_string(), None))
+                            .await;
+                    }
                     "TOGGLE_TYPE" => {
                         let _ = tx
-                            .send(AppAction::ToggleRecording("TYPE".to_string(), false))
+                            .send(AppAction::ToggleRecording("TYPE".to_string(), None))
                             .await;
                     }
                     "TOGGLE_COPY" => {
                         let _ = tx
-                            .send(AppAction::ToggleRecording("COPY".to_string(), false))
+                            .send(AppAction::ToggleRecording("COPY".to_string(), None))
                             .await;
                     }
                     "CANCEL" => {
                         let _ = tx.send(AppAction::CancelRecording).await;
                     }
-                    "AUTO_STOP" => {
-                        let _ = tx
-                            .send(AppAction::ToggleRecording("AUTO".to_string(), true))
-                            .await;
+                    "DICTATE_START" => {
+                        let _ = tx.send(AppAction::SetDictation(true)).await;
+                    }
+                    "DICTATE_STOP" => {
+                        let _ = tx.send(AppAction::SetDictation(false)).await;
                     }
                     _ => {}
                 }
@@ -1,14 +1,19 @@
 use gtk4::prelude::*;
-use gtk4::{Application, ApplicationWindow, CssProvider, Label};
+use gtk4::{Application, ApplicationWindow, Box as GtkBox, CssProvider, Label, Orientation};
 use gtk4_layer_shell::{Edge, KeyboardMode, Layer, LayerShell};
 
 #[derive(Clone)]
 pub struct Osd {
     window: ApplicationWindow,
     label: Label,
+    partial: Label,
+    profile: Label,
     provider: CssProvider,
 }
 
+// Only the end of a long partial transcript fits in the OSD
+const PARTIAL_MAX_CHARS: usize = 120;
+
 impl Osd {
     pub fn new(app: &Application) -> Self {
         let window = ApplicationWindow::builder()
@@ -39,6 +44,24 @@ impl Osd {
         label.set_margin_start(20);
         label.set_margin_end(20);
 
+        let partial = Label::new(None);
+        partial.set_wrap(true);
+        partial.set_max_width_chars(40);
+        partial.set_margin_bottom(10);
+        partial.set_margin_start(20);
+        partial.set_margin_end(20);
+        partial.set_visible(false);
+
+        let profile = Label::new(None);
+        profile.set_margin_bottom(6);
+        profile.set_opacity(0.7);
+        profile.set_visible(false);
+
+        let container = GtkBox::new(Orientation::Vertical, 0);
+        container.append(&label);
+        container.append(&partial);
+        container.append(&profile);
+
         // Initial CSS
         let provider = CssProvider::new();
         provider.load_from_data("window { background-color: black; color: white; font-weight: bold; padding: 10px; border-radius: 8px; font-size: 14px; } label { color: white; }");
@@ -46,17 +69,27 @@ impl Osd {
         let context = window.style_context();
         context.add_provider(&provider, gtk4::STYLE_PROVIDER_PRIORITY_APPLICATION);
 
-        window.set_child(Some(&label));
+        window.set_child(Some(&container));
 
         Self {
             window,
             label,
+            partial,
+            profile,
             provider,
         }
     }
 
+    /// Names the active profile under the status text; "default" shows nothing.
+    pub fn set_profile(&self, name: &str) {
+        let visible = !name.is_empty() && name != "default";
+        self.profile.set_text(&format!("Perfil: {}", name));
+        self.profile.set_visible(visible);
+    }
+
     pub fn show(&self, text: &str, color: &str) {
         self.label.set_text(text);
+        self.partial.set_visible(false);
 
         let css = format!(
             "window {{ background-color: {}; color: white; font-weight: bold; border-radius: 8px; font-size: 14px; }}",
@@ -67,6 +100,18 @@ impl Osd {
         self.window.present();
     }
 
+    pub fn show_partial(&self, text: &str) {
+        let count = text.chars().count();
+        let tail: String = if count > PARTIAL_MAX_CHARS {
+            let skipped: String = text.chars().skip(count - PARTIAL_MAX_CHARS).collect();
+            format!("…{}", skipped)
+        } else {
+            text.to_string()
+        };
+        self.partial.set_text(&tail);
+        self.partial.set_visible(true);
+    }
+
     pub fn hide(&self) {
         self.window.set_visible(false);
     }